@@ -0,0 +1,10 @@
+CREATE TABLE rank_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    list VARCHAR NOT NULL,
+    membership_id BIGINT NOT NULL,
+    position INTEGER NOT NULL,
+    UNIQUE (list, membership_id, created_at)
+);
+
+CREATE INDEX rank_history_member ON rank_history (membership_id, created_at);
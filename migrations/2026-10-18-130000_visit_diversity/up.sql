@@ -0,0 +1,10 @@
+CREATE TABLE visit_diversity (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    membership_id BIGINT NOT NULL,
+    visitor_type INTEGER NOT NULL,
+    dimension VARCHAR NOT NULL,
+    value VARCHAR NOT NULL,
+    count BIGINT NOT NULL,
+    UNIQUE (membership_id, created_at, visitor_type, dimension, value)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE lifecycle_transitions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    membership_id BIGINT NOT NULL,
+    from_state VARCHAR NOT NULL,
+    to_state VARCHAR NOT NULL,
+    idle_days INTEGER NOT NULL,
+    last_active_at TIMESTAMP
+);
+CREATE INDEX lifecycle_transitions_member ON lifecycle_transitions (membership_id, created_at);
@@ -0,0 +1 @@
+DROP TABLE referral_edges;
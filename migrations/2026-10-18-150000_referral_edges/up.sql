@@ -0,0 +1,9 @@
+CREATE TABLE referral_edges (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    source_id BIGINT NOT NULL,
+    target_id BIGINT NOT NULL,
+    visitor_type INTEGER NOT NULL,
+    count BIGINT NOT NULL,
+    UNIQUE (created_at, source_id, target_id, visitor_type)
+);
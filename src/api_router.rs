@@ -0,0 +1,841 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Extension, Path, Query},
+    http::{header::HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::Serialize;
+
+use crate::{
+    app_model::DynContext,
+    clock::start_of_day,
+    embed::{self, EmbedSnippets},
+    i18n::Lang,
+    level_model::{
+        daily_level_tables, LevelTable, LevelThreshold, LEVEL_FORMULA, LEVEL_WINDOW_DAYS,
+    },
+    lifecycle_model::{state_of, LifecycleState, LifecycleThresholds, LifecycleTransition},
+    membership_model::{Membership, MEMBERSHIP_PATH},
+    quality_model::{CountryCount, QualityScore, VisitDiversity},
+    rank_history_model::RankHistory,
+    rank_listing::{list_members, ListingItem, ListingQuery},
+    referral_model::{referral_kind, ReferralEdge},
+    statistics_io::{self, ExportFilter, ExportFormat},
+    statistics_model::Statistics,
+    svg_template::ThemeQuery,
+    ADMIN_TOKEN,
+};
+
+// 统计接口缺省返回的天数与最大跨度
+const STATS_DEFAULT_DAYS: i64 = 30;
+const STATS_MAX_DAYS: i64 = 366 * 3;
+// 与写库间隔一致
+pub(crate) const STATS_MAX_AGE: u32 = 300;
+// 审核列表缺省统计的天数
+const REVIEW_DEFAULT_DAYS: i64 = 7;
+// 引流关系缺省统计的天数
+pub(crate) const REFERRAL_DEFAULT_DAYS: i64 = 30;
+// 生命周期接口缺省返回的状态变化天数
+const LIFECYCLE_DEFAULT_DAYS: i64 = 30;
+
+// 校验 Authorization: Bearer <ADMIN_TOKEN>
+fn authorized(headers: &HeaderMap) -> bool {
+    let token = match ADMIN_TOKEN.as_ref() {
+        Some(token) => token,
+        None => return false,
+    };
+    headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| v.eq(token))
+}
+
+fn not_found(msg: &str) -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        [("content-type", "text/plain")],
+        msg.to_string(),
+    )
+        .into_response()
+}
+
+// 带缓存头的 JSON 响应，ETag 与 If-None-Match 一致时返回 304
+pub(crate) fn cached_json<T: Serialize>(headers: &HeaderMap, value: &T, max_age: u32) -> Response {
+    let body = match serde_json::to_string(value) {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("W/\"{:x}\"", hasher.finish());
+    let cache_control = format!("public, max-age={}", max_age);
+
+    let not_modified = headers
+        .get("If-None-Match")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim().eq(&etag)));
+    if not_modified {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [("etag", etag), ("cache-control", cache_control)],
+        )
+            .into_response();
+    }
+    (
+        StatusCode::OK,
+        [
+            ("content-type", "application/json".to_string()),
+            ("etag", etag),
+            ("cache-control", cache_control),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn bad_request(msg: String) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        [("content-type", "text/plain")],
+        msg,
+    )
+        .into_response()
+}
+
+pub(crate) fn parse_date(
+    query: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<NaiveDate>, String> {
+    match query.get(key).filter(|v| !v.is_empty()) {
+        Some(v) => NaiveDate::parse_from_str(v, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("invalid {}, expected YYYY-MM-DD", key)),
+        None => Ok(None),
+    }
+}
+
+pub async fn export_statistics(
+    Extension(ctx): Extension<DynContext>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    }
+
+    let format = match ExportFormat::from_str(query.get("format").map_or("csv", |f| f.as_str())) {
+        Ok(format) => format,
+        Err(e) => return bad_request(e.to_string()),
+    };
+    let (from, to) = match (parse_date(&query, "from"), parse_date(&query, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+    };
+
+    // 导出需要包含隐藏成员的历史数据
+    let members = Membership::load_all(MEMBERSHIP_PATH).unwrap_or_else(|_| ctx.id2member.clone());
+    let membership_id = match query.get("member").filter(|v| !v.is_empty()) {
+        Some(key) => match statistics_io::find_member(&members, key) {
+            Some(m) => Some(m.id),
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    [("content-type", "text/plain")],
+                    "not a member",
+                )
+                    .into_response()
+            }
+        },
+        None => None,
+    };
+
+    let mut body = Vec::new();
+    let filter = ExportFilter::new(membership_id, from, to);
+    if let Err(e) = statistics_io::export(&ctx.db_pool, &members, &filter, format, &mut body) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+    }
+
+    (
+        StatusCode::OK,
+        [
+            ("content-type", format.content_type().to_string()),
+            (
+                "content-disposition",
+                format!("attachment; filename=\"statistics.{}\"", format.extension()),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Granularity {
+    Day,
+    Month,
+}
+
+impl Granularity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Month => "month",
+        }
+    }
+}
+
+/// 时间序列中的一个点
+#[derive(Serialize)]
+pub struct StatsPoint {
+    /// 日粒度为 `YYYY-MM-DD`，月粒度为 `YYYY-MM`
+    pub date: String,
+    pub unique_visitor: i64,
+    pub referrer: i64,
+    /// 日粒度为当日等级，月粒度为当月各日等级的四舍五入均值
+    pub level: i64,
+}
+
+/// 某一天收盘时在某个榜单上的名次
+#[derive(Serialize)]
+pub struct RankPoint {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub list: String,
+    /// 从 1 开始
+    pub position: i32,
+}
+
+/// `GET /api/stats/:domain` 的响应，字段说明见 docs/api.md
+#[derive(Serialize)]
+pub struct MemberStats {
+    pub domain: String,
+    pub name: String,
+    pub granularity: &'static str,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub unique_visitor: i64,
+    pub referrer: i64,
+    pub series: Vec<StatsPoint>,
+    /// 不受 granularity 影响，始终按日
+    pub rank_history: Vec<RankPoint>,
+    pub lifecycle: LifecycleState,
+    /// 区间内按国家的访问，按访问总数从多到少
+    pub countries: Vec<CountryCount>,
+}
+
+pub async fn member_stats(
+    Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let id = match ctx.domain2id.get(&domain) {
+        Some(id) => *id,
+        None => return not_found("not a member"),
+    };
+    let granularity = match query.get("granularity").map(|g| g.as_str()) {
+        None | Some("") | Some("day") => Granularity::Day,
+        Some("month") => Granularity::Month,
+        Some(g) => return bad_request(format!("invalid granularity {}, expected day or month", g)),
+    };
+    let today = ctx.clock.today();
+    let (from, to) = match (parse_date(&query, "from"), parse_date(&query, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+    };
+    let to = to.unwrap_or(today).min(today);
+    let from = from.unwrap_or(to - Duration::days(STATS_DEFAULT_DAYS - 1));
+    if from > to || (to - from).num_days() >= STATS_MAX_DAYS {
+        return bad_request(format!(
+            "invalid range, from must not be after to and span at most {} days",
+            STATS_MAX_DAYS
+        ));
+    }
+
+    let rows = Statistics::between(
+        ctx.db_pool.get().unwrap(),
+        Some(id),
+        start_of_day(from),
+        start_of_day(to),
+    );
+    let levels = daily_level_tables(ctx.db_pool.get().unwrap(), from, to);
+    let history = ctx
+        .db_pool
+        .get()
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut conn| {
+            RankHistory::of_member(&mut conn, id, start_of_day(from), start_of_day(to))
+        });
+    let countries = ctx
+        .db_pool
+        .get()
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut conn| {
+            VisitDiversity::countries_between(
+                &mut conn,
+                Some(id),
+                start_of_day(from),
+                start_of_day(to),
+            )
+        });
+    let (rows, levels, history, countries) = match (rows, levels, history, countries) {
+        (Ok(rows), Ok(levels), Ok(history), Ok(countries)) => (rows, levels, history, countries),
+        (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    };
+
+    let mut days: BTreeMap<NaiveDate, (i64, i64, i64)> = BTreeMap::new();
+    let mut day = from;
+    while day <= to {
+        days.insert(day, (0, 0, 0));
+        day += Duration::days(1);
+    }
+    rows.iter().for_each(|s| {
+        days.insert(s.created_at.date(), (s.unique_visitor, s.referrer, 0));
+    });
+    days.iter_mut().for_each(|(d, v)| {
+        v.2 = levels.get(d).map_or(1, |t| t.level_of(&id));
+    });
+
+    // 合并当天尚未写库的内存计数
+    let counters = ctx.counters.read().await;
+    if let Some(v) = days.get_mut(&counters.day) {
+        v.0 = counters.unique_visitor_of(&id).0;
+        v.1 = counters.referrer_of(&id).0;
+    }
+    drop(counters);
+
+    let series = match granularity {
+        Granularity::Day => days
+            .iter()
+            .map(|(d, v)| StatsPoint {
+                date: d.format("%Y-%m-%d").to_string(),
+                unique_visitor: v.0,
+                referrer: v.1,
+                level: v.2,
+            })
+            .collect::<Vec<_>>(),
+        Granularity::Month => {
+            let mut months: BTreeMap<(i32, u32), (i64, i64, i64, i64)> = BTreeMap::new();
+            days.iter().for_each(|(d, v)| {
+                let m = months.entry((d.year(), d.month())).or_default();
+                m.0 += v.0;
+                m.1 += v.1;
+                m.2 += v.2;
+                m.3 += 1;
+            });
+            months
+                .iter()
+                .map(|((y, m), v)| StatsPoint {
+                    date: format!("{:04}-{:02}", y, m),
+                    unique_visitor: v.0,
+                    referrer: v.1,
+                    level: (v.2 as f64 / v.3 as f64).round() as i64,
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let member = ctx.id2member.get(&id).unwrap();
+    cached_json(
+        &headers,
+        &MemberStats {
+            domain: member.domain.clone(),
+            name: member.name.clone(),
+            granularity: granularity.as_str(),
+            from,
+            to,
+            unique_visitor: series.iter().map(|p| p.unique_visitor).sum(),
+            referrer: series.iter().map(|p| p.referrer).sum(),
+            series,
+            rank_history: history
+                .into_iter()
+                .map(|h| RankPoint {
+                    date: h.created_at.format("%Y-%m-%d").to_string(),
+                    list: h.list,
+                    position: h.position,
+                })
+                .collect(),
+            lifecycle: ctx.lifecycle_of(&id).await,
+            countries,
+        },
+        STATS_MAX_AGE,
+    )
+}
+
+/// `GET /api/shields/:domain` 的响应，格式见 https://shields.io/badges/endpoint-badge
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShieldsEndpoint {
+    pub schema_version: u8,
+    pub label: String,
+    pub message: String,
+    pub color: String,
+    pub cache_seconds: u32,
+}
+
+// shields.io 的服务器代为请求，没有访客信息，只读取计数
+pub async fn shields_endpoint(
+    Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let id = match ctx.domain2id.get(&domain) {
+        Some(id) => *id,
+        None => return not_found("not a member"),
+    };
+    let (theme, lang) = match (ThemeQuery::parse(&query), Lang::negotiate(&query, &headers)) {
+        (Ok(theme), Ok(lang)) => (theme, lang),
+        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+    };
+    let text = lang.text();
+    let (uv, rv, level) = ctx.counts_of(&id).await;
+    let (label, message) = match query.get("metric").map(|m| m.as_str()) {
+        None | Some("") | Some("uv") => (text.unique_visitor, uv),
+        Some("referrer") => (text.referrer, rv),
+        Some("level") => (text.level, level),
+        Some(m) => {
+            return bad_request(format!(
+                "invalid metric {}, expected uv, referrer or level",
+                m
+            ))
+        }
+    };
+    let member = ctx.id2member.get(&id).unwrap();
+    cached_json(
+        &headers,
+        &ShieldsEndpoint {
+            schema_version: 1,
+            label: format!("{} {}", text.brand, label),
+            message: message.to_string(),
+            color: theme
+                .resolve(member)
+                .accent
+                .trim_start_matches('#')
+                .to_string(),
+            cache_seconds: STATS_MAX_AGE,
+        },
+        STATS_MAX_AGE,
+    )
+}
+
+// 加入页面生成的嵌入代码；令牌不是秘密，任何人都能取得成员的令牌，见 docs/api.md
+pub async fn embed_snippets(
+    Path(domain): Path<String>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    if ctx.domain2id.contains_key(&domain) {
+        return cached_json(&headers, &EmbedSnippets::new(&domain), STATS_MAX_AGE);
+    }
+    let domain = domain.to_ascii_lowercase();
+    if !embed::plausible_domain(&domain) {
+        return bad_request(format!("invalid domain {}", domain));
+    }
+    cached_json(&headers, &EmbedSnippets::preview(&domain), STATS_MAX_AGE)
+}
+
+/// `GET /api/levels` 中指定成员的得分
+#[derive(Serialize)]
+pub struct MemberLevel {
+    pub domain: String,
+    pub score: f64,
+    pub level: i64,
+}
+
+/// `GET /api/levels` 的响应，说明当天的等级是怎么算出来的
+#[derive(Serialize)]
+pub struct LevelsExplain {
+    pub day: NaiveDate,
+    pub window_from: NaiveDate,
+    pub window_to: NaiveDate,
+    pub window_days: i64,
+    pub formula: &'static str,
+    pub active_members: usize,
+    pub thresholds: Vec<LevelThreshold>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<MemberLevel>,
+}
+
+pub async fn levels_explain(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let levels = ctx.levels.read().await.clone();
+    let member = match query.get("domain").filter(|v| !v.is_empty()) {
+        Some(domain) => match ctx.domain2id.get(domain) {
+            Some(id) => Some(MemberLevel {
+                domain: domain.clone(),
+                score: levels.score_of(id),
+                level: levels.level_of(id),
+            }),
+            None => return not_found("not a member"),
+        },
+        None => None,
+    };
+    let (window_from, window_to) = LevelTable::window(levels.day);
+    cached_json(
+        &headers,
+        &LevelsExplain {
+            day: levels.day,
+            window_from,
+            window_to,
+            window_days: LEVEL_WINDOW_DAYS,
+            formula: LEVEL_FORMULA,
+            active_members: levels.scores.len(),
+            thresholds: levels.thresholds(),
+            member,
+        },
+        STATS_MAX_AGE,
+    )
+}
+
+/// `GET /api/review` 中一个待审核的成员
+#[derive(Serialize)]
+pub struct ReviewItem {
+    pub domain: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub quality: QualityScore,
+}
+
+/// `GET /api/review` 的响应
+#[derive(Serialize)]
+pub struct Review {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub members: Vec<ReviewItem>,
+}
+
+// 访问来源过于集中、被标记待审核的成员，需要管理员令牌
+pub async fn review_members(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    if !authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    }
+    let today = ctx.clock.today();
+    let (from, to) = match (parse_date(&query, "from"), parse_date(&query, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return bad_request(e),
+    };
+    let to = to.unwrap_or(today).min(today);
+    let from = from.unwrap_or(to - Duration::days(REVIEW_DEFAULT_DAYS - 1));
+    if from > to || (to - from).num_days() >= STATS_MAX_DAYS {
+        return bad_request(format!(
+            "invalid range, from must not be after to and span at most {} days",
+            STATS_MAX_DAYS
+        ));
+    }
+
+    let quality = match ctx.rank_cache.quality(&ctx.db_pool, (from, to)).await {
+        Ok(quality) => quality,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let mut members: Vec<ReviewItem> = quality
+        .iter()
+        .filter(|(_, q)| q.suspicious())
+        .filter_map(|(id, q)| {
+            ctx.id2member.get(id).map(|m| ReviewItem {
+                domain: m.domain.clone(),
+                name: m.name.clone(),
+                quality: q.clone(),
+            })
+        })
+        .collect();
+    members.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+    (
+        StatusCode::OK,
+        [("content-type", "application/json")],
+        serde_json::to_string(&Review { from, to, members }).unwrap(),
+    )
+        .into_response()
+}
+
+/// `GET /api/rank` 的响应，参数与排行榜页相同
+#[derive(Serialize)]
+pub struct RankListing {
+    pub sort: &'static str,
+    pub order: &'static str,
+    pub q: String,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+    pub pages: usize,
+    pub members: Vec<ListingItem>,
+}
+
+pub async fn rank_listing(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let query = match ListingQuery::parse(&query) {
+        Ok(query) => query,
+        Err(e) => return bad_request(e),
+    };
+    let listing = match list_members(&ctx, &query).await {
+        Ok(listing) => listing,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    cached_json(
+        &headers,
+        &RankListing {
+            sort: query.sort.as_str(),
+            order: if query.desc { "desc" } else { "asc" },
+            q: query.q.clone(),
+            page: query.page,
+            per_page: query.per_page,
+            total: listing.total,
+            pages: listing.pages,
+            members: listing.members.iter().map(ListingItem::from).collect(),
+        },
+        STATS_MAX_AGE,
+    )
+}
+
+/// `GET /api/lifecycle` 中一个非活跃状态的成员
+#[derive(Serialize)]
+pub struct LifecycleMember {
+    pub domain: String,
+    pub name: String,
+    pub state: LifecycleState,
+}
+
+/// `GET /api/lifecycle` 中的一次状态变化
+#[derive(Serialize)]
+pub struct LifecycleChange {
+    pub domain: String,
+    #[serde(flatten)]
+    pub transition: LifecycleTransition,
+}
+
+/// `GET /api/lifecycle` 的响应
+#[derive(Serialize)]
+pub struct Lifecycle {
+    pub thresholds: LifecycleThresholds,
+    pub members: Vec<LifecycleMember>,
+    pub transitions: Vec<LifecycleChange>,
+}
+
+// 非活跃成员及近期的状态变化，需要管理员令牌；?domain= 时返回该成员的全部变化
+pub async fn lifecycle_members(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    if !authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "unauthorized").into_response();
+    }
+    let member = match query.get("domain").filter(|d| !d.is_empty()) {
+        Some(domain) => match ctx.domain2id.get(domain) {
+            Some(id) => Some(*id),
+            None => return not_found("not a member"),
+        },
+        None => None,
+    };
+    let days = match query.get("days").filter(|d| !d.is_empty()) {
+        Some(d) => match d
+            .parse::<i64>()
+            .ok()
+            .filter(|d| *d > 0 && *d < STATS_MAX_DAYS)
+        {
+            Some(d) => d,
+            None => {
+                return bad_request(format!(
+                    "invalid days, expected 1 to {}",
+                    STATS_MAX_DAYS - 1
+                ))
+            }
+        },
+        None => LIFECYCLE_DEFAULT_DAYS,
+    };
+
+    let start = start_of_day(ctx.clock.today() - Duration::days(days - 1));
+    let transitions = ctx
+        .db_pool
+        .get()
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut conn| match member {
+            Some(member) => LifecycleTransition::of_member(&mut conn, member),
+            None => LifecycleTransition::since(&mut conn, start),
+        });
+    let transitions = match transitions {
+        Ok(transitions) => transitions,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let states = ctx.lifecycle_states().await;
+    let mut members: Vec<LifecycleMember> = ctx
+        .id2member
+        .iter()
+        .filter(|(id, _)| member.is_none_or(|m| m == **id))
+        .map(|(id, m)| LifecycleMember {
+            domain: m.domain.clone(),
+            name: m.name.clone(),
+            state: state_of(&states, id),
+        })
+        .filter(|m| member.is_some() || m.state != LifecycleState::Active)
+        .collect();
+    members.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+    (
+        StatusCode::OK,
+        [("content-type", "application/json")],
+        serde_json::to_string(&Lifecycle {
+            thresholds: ctx.lifecycle_thresholds,
+            members,
+            transitions: transitions
+                .into_iter()
+                .filter_map(|t| {
+                    ctx.id2member
+                        .get(&t.membership_id)
+                        .map(|m| LifecycleChange {
+                            domain: m.domain.clone(),
+                            transition: t,
+                        })
+                })
+                .collect(),
+        })
+        .unwrap(),
+    )
+        .into_response()
+}
+
+/// 引流关系中的一个成员
+#[derive(Serialize)]
+pub struct ReferralNode {
+    pub domain: String,
+    pub name: String,
+    /// 带给其他成员的访问
+    pub sent: i64,
+    /// 从其他成员得到的访问
+    pub received: i64,
+}
+
+/// 引流关系中的一条边
+#[derive(Serialize)]
+pub struct ReferralLink {
+    pub source: String,
+    pub target: String,
+    /// `click`、`badge`、`card`、`icon`、`favicon`
+    pub kind: &'static str,
+    pub count: i64,
+}
+
+/// `GET /api/referrals` 的响应，与 `/referrals` 页面共用
+#[derive(Serialize)]
+pub struct ReferralGraph {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub members: Vec<ReferralNode>,
+    pub edges: Vec<ReferralLink>,
+}
+
+// 解析 from、to、domain 并汇总区间内的引流；已不在册的成员不显示
+pub(crate) fn referral_graph(
+    ctx: &DynContext,
+    query: &HashMap<String, String>,
+) -> Result<ReferralGraph, (StatusCode, String)> {
+    let member = match query.get("domain").filter(|d| !d.is_empty()) {
+        Some(domain) => match ctx.domain2id.get(domain) {
+            Some(id) => Some(*id),
+            None => return Err((StatusCode::NOT_FOUND, "not a member".to_string())),
+        },
+        None => None,
+    };
+    let today = ctx.clock.today();
+    let (from, to) = match (parse_date(query, "from"), parse_date(query, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return Err((StatusCode::BAD_REQUEST, e)),
+    };
+    let to = to.unwrap_or(today).min(today);
+    let from = from.unwrap_or(to - Duration::days(REFERRAL_DEFAULT_DAYS - 1));
+    if from > to || (to - from).num_days() >= STATS_MAX_DAYS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "invalid range, from must not be after to and span at most {} days",
+                STATS_MAX_DAYS
+            ),
+        ));
+    }
+
+    let edges = ctx
+        .db_pool
+        .get()
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut conn| {
+            ReferralEdge::between(&mut conn, member, start_of_day(from), start_of_day(to))
+        })
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let edges: Vec<ReferralEdge> = edges
+        .into_iter()
+        .filter(|e| {
+            ctx.id2member.contains_key(&e.source_id) && ctx.id2member.contains_key(&e.target_id)
+        })
+        .collect();
+
+    let mut totals: HashMap<i64, (i64, i64)> = HashMap::new();
+    edges.iter().for_each(|e| {
+        totals.entry(e.source_id).or_default().0 += e.count;
+        totals.entry(e.target_id).or_default().1 += e.count;
+    });
+    let mut members: Vec<ReferralNode> = totals
+        .into_iter()
+        .map(|(id, (sent, received))| {
+            let m = ctx.id2member.get(&id).unwrap();
+            ReferralNode {
+                domain: m.domain.clone(),
+                name: m.name.clone(),
+                sent,
+                received,
+            }
+        })
+        .collect();
+    members.sort_by(|a, b| {
+        (b.sent + b.received)
+            .cmp(&(a.sent + a.received))
+            .then(a.domain.cmp(&b.domain))
+    });
+
+    Ok(ReferralGraph {
+        from,
+        to,
+        members,
+        edges: edges
+            .iter()
+            .map(|e| ReferralLink {
+                source: ctx.id2member.get(&e.source_id).unwrap().domain.clone(),
+                target: ctx.id2member.get(&e.target_id).unwrap().domain.clone(),
+                kind: referral_kind(e.visitor_type),
+                count: e.count,
+            })
+            .collect(),
+    })
+}
+
+pub async fn referral_edges(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    match referral_graph(&ctx, &query) {
+        Ok(graph) => cached_json(&headers, &graph, STATS_MAX_AGE),
+        Err(res) => res.into_response(),
+    }
+}
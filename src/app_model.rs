@@ -1,22 +1,41 @@
-use std::fs;
 use std::time::Duration;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, mem, sync::Arc};
 
+use crate::chart::ChartCache;
+use crate::clock::{next_wakeup, start_of_day, Clock, SystemClock};
+use crate::heatmap::HeatmapCache;
+use crate::level_model::LevelTable;
+use crate::lifecycle_model::{
+    notify, state_of, LifecycleEvent, LifecycleState, LifecycleStates, LifecycleThresholds,
+    LifecycleTransition,
+};
+use crate::quality_model::{
+    country_code, network_of, user_agent_digest, DiversityKey, VisitDiversity, DIMENSION_COUNTRY,
+    DIMENSION_NETWORK, DIMENSION_USER_AGENT,
+};
+use crate::rank_cache::{RankCache, RankPeriod};
+use crate::rank_history_model::{RankHistory, RankMovement, SNAPSHOT_LISTS};
+use crate::rank_strategy::{last_active, RankContext, RankStrategies};
+use crate::referral_model::{ReferralEdge, ReferralKey};
 use crate::statistics_model::Statistics;
 use crate::DbPool;
-use crate::{now_shanghai, SYSTEM_DOMAIN};
+use crate::SYSTEM_DOMAIN;
+use diesel::Connection;
 
-use crate::membership_model::Membership;
+use crate::avatar::{AvatarStore, AVATAR_DIR};
+use crate::membership_model::{Membership, MEMBERSHIP_PATH};
+use crate::png_render::{CounterState, PngCache};
+use crate::sparkline::SparklineCache;
 use anyhow::anyhow;
 use axum::http::{HeaderMap, HeaderValue};
-use chrono::{NaiveDateTime, NaiveTime};
+use chrono::{NaiveDate, NaiveDateTime};
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::Serialize;
 use serde_repr::*;
 use tokio::sync::watch::{self, Receiver, Sender};
-use tokio::sync::RwLock;
-use tracing::info;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{error, info};
 
 pub type DynContext = Arc<Context>;
 
@@ -44,11 +63,135 @@ pub enum VisitorType {
     Card = 5,
 }
 
+// 定时写库的间隔（分钟）
+const FLUSH_INTERVAL_MINUTES: u32 = 5;
+// 从成员站点来到联盟后，多久内的点击仍算作该成员的引流
+const ARRIVAL_TTL: Duration = Duration::from_secs(60 * 60 * 4);
+// 每次写库后预先计算的榜单
+const WARM_RANK_PERIODS: [RankPeriod; 2] = [RankPeriod::AllTime, RankPeriod::Monthly];
+
+// 某一天的内存计数，跨天时整体换出，保证每次访问都记在正确的日期。
+// unique_visitor/referrer 为所有实例的合计（上次写库后读回的数据库合计 + 本实例未写库的增量），
+// 写库只写增量，多个实例共用同一数据库时不会互相覆盖
+#[derive(Clone)]
+pub struct DailyCounters {
+    pub day: NaiveDate,
+    pub unique_visitor: HashMap<i64, (i64, NaiveDateTime)>,
+    pub referrer: HashMap<i64, (i64, NaiveDateTime)>,
+    // 本实例上次写库后新增的 (uv, rv)
+    pending: HashMap<i64, (i64, i64)>,
+    // 本实例上次写库后新增计数的来源分布（网段、国家、UA）
+    diversity: HashMap<DiversityKey, i64>,
+    // 本实例上次写库后新增的成员间引流
+    referrals: HashMap<ReferralKey, i64>,
+}
+
+impl DailyCounters {
+    pub fn new(day: NaiveDate) -> Self {
+        DailyCounters {
+            day,
+            unique_visitor: HashMap::new(),
+            referrer: HashMap::new(),
+            pending: HashMap::new(),
+            diversity: HashMap::new(),
+            referrals: HashMap::new(),
+        }
+    }
+
+    pub fn unique_visitor_of(&self, id: &i64) -> (i64, NaiveDateTime) {
+        *self
+            .unique_visitor
+            .get(id)
+            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
+    }
+
+    pub fn referrer_of(&self, id: &i64) -> (i64, NaiveDateTime) {
+        *self
+            .referrer
+            .get(id)
+            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
+    }
+
+    fn increment_of(&self, id: &i64, delta: &(i64, i64)) -> Statistics {
+        Statistics {
+            created_at: start_of_day(self.day),
+            membership_id: *id,
+            unique_visitor: delta.0,
+            updated_at: self.unique_visitor_of(id).1,
+            referrer: delta.1,
+            latest_referrer_at: Some(self.referrer_of(id).1),
+            id: 0,
+        }
+    }
+
+    fn record_source(&mut self, id: i64, visitor_type: i32, sources: &[(&'static str, String)]) {
+        sources.iter().for_each(|(dimension, value)| {
+            *self
+                .diversity
+                .entry(DiversityKey {
+                    membership_id: id,
+                    visitor_type,
+                    dimension,
+                    value: value.clone(),
+                })
+                .or_default() += 1;
+        });
+    }
+
+    fn record_referral(&mut self, source: i64, target: i64, visitor_type: i32) {
+        *self
+            .referrals
+            .entry(ReferralKey {
+                source_id: source,
+                target_id: target,
+                visitor_type,
+            })
+            .or_default() += 1;
+    }
+
+    fn increments(&self) -> Vec<Statistics> {
+        self.pending
+            .iter()
+            .map(|(id, delta)| self.increment_of(id, delta))
+            .collect()
+    }
+
+    // 以数据库合计为准，再加上写库期间本实例新产生的增量
+    fn rebase(&mut self, stored: &[Statistics]) {
+        stored.iter().for_each(|s| {
+            let delta = self
+                .pending
+                .get(&s.membership_id)
+                .cloned()
+                .unwrap_or_default();
+            let uv = self.unique_visitor_of(&s.membership_id);
+            let rv = self.referrer_of(&s.membership_id);
+            self.unique_visitor.insert(
+                s.membership_id,
+                (s.unique_visitor + delta.0, uv.1.max(s.updated_at)),
+            );
+            self.referrer.insert(
+                s.membership_id,
+                (
+                    s.referrer + delta.1,
+                    rv.1.max(
+                        s.latest_referrer_at
+                            .unwrap_or(NaiveDateTime::from_timestamp(0, 0)),
+                    ),
+                ),
+            );
+        });
+    }
+}
+
 pub struct Context {
     pub db_pool: DbPool,
-    pub unique_visitor: RwLock<HashMap<i64, (i64, NaiveDateTime)>>,
-    pub referrer: RwLock<HashMap<i64, (i64, NaiveDateTime)>>,
-    pub rank_avg: RwLock<i64>,
+    pub clock: Arc<dyn Clock>,
+    pub counters: RwLock<DailyCounters>,
+    // 已跨天换出、尚未写库的计数
+    closed_days: Mutex<Vec<DailyCounters>>,
+    // 当天的等级表，跨天写库后重新计算
+    pub levels: RwLock<Arc<LevelTable>>,
 
     pub domain2id: HashMap<String, i64>,
     pub id2member: HashMap<i64, Membership>,
@@ -56,21 +199,122 @@ pub struct Context {
     pub visitor_tx: Sender<String>,
     pub visitor_rx: Receiver<String>,
 
-    pub rank: RwLock<Vec<Statistics>>,
-    pub monthly_rank: RwLock<Vec<Statistics>>,
+    pub rank_cache: RankCache,
+    pub rank_strategies: RankStrategies,
+    // 各榜单上一次快照的名次，定时任务执行时清空
+    prev_positions: RwLock<HashMap<String, Arc<HashMap<i64, i32>>>>,
+    // 成员的生命周期状态，定时任务中按未活跃天数更新
+    lifecycle: RwLock<Arc<LifecycleStates>>,
+    pub lifecycle_thresholds: LifecycleThresholds,
+    // 状态变化时通知的地址，环境变量 LIFECYCLE_WEBHOOK_URL
+    lifecycle_webhook: Option<String>,
 
     pub cache: r_cache::cache::Cache<String, ()>,
+    // 访客 IP 最近一次从哪个成员的站点来到联盟，用于记录点击引流
+    arrivals: r_cache::cache::Cache<String, i64>,
+    // 徽章、卡片、图标的 PNG
+    pub png_cache: PngCache,
+    // 卡片趋势图用到的历史计数
+    pub sparklines: SparklineCache,
+    pub heatmaps: HeatmapCache,
+    pub charts: ChartCache,
+    pub avatars: AvatarStore,
 }
 
 impl Context {
-    pub async fn get_tend_from_uv_and_rv(&self, uv: i64, rv: i64) -> i64 {
-        let tend = (uv + rv) / self.rank_avg.read().await.to_owned();
-        if tend > 10 {
-            return 10;
-        } else if tend < 1 {
-            return 1;
+    // 等级只取决于截至昨天的窗口，当天内不变
+    pub async fn level_of(&self, id: &i64) -> i64 {
+        self.levels.read().await.level_of(id)
+    }
+
+    pub async fn lifecycle_of(&self, id: &i64) -> LifecycleState {
+        state_of(&**self.lifecycle.read().await, id)
+    }
+
+    // 当前状态的快照，逐个成员判断时不必反复加锁
+    pub async fn lifecycle_states(&self) -> Arc<LifecycleStates> {
+        self.lifecycle.read().await.clone()
+    }
+
+    // 按全部榜单中的最后活跃时间推进各成员的状态，加入日期晚于最后活跃时以加入日期为准；
+    // 没有统计也没有加入日期的成员保持不变
+    async fn refresh_lifecycle(&self, now: NaiveDateTime) {
+        let all = match self
+            .rank_cache
+            .get(&self.db_pool, RankPeriod::AllTime, now)
+            .await
+        {
+            Ok(all) => all,
+            Err(e) => {
+                error!("load rank for lifecycle: {}", e);
+                return;
+            }
+        };
+        let mut active_at: HashMap<i64, NaiveDateTime> = self
+            .id2member
+            .iter()
+            .filter_map(|(member, m)| m.joined_at.map(|d| (*member, start_of_day(d))))
+            .collect();
+        all.iter()
+            .filter(|s| self.id2member.contains_key(&s.membership_id))
+            .for_each(|s| {
+                let at = active_at.entry(s.membership_id).or_insert(last_active(s));
+                *at = (*at).max(last_active(s));
+            });
+
+        let res = self
+            .db_pool
+            .get()
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|mut conn| {
+                LifecycleTransition::advance(&mut conn, &self.lifecycle_thresholds, &active_at, now)
+            });
+        let (states, changes) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                error!("advance lifecycle: {}", e);
+                return;
+            }
+        };
+        *self.lifecycle.write().await = Arc::new(states);
+        if changes.is_empty() {
+            return;
+        }
+        changes.iter().for_each(|t| {
+            info!(
+                "lifecycle {} {} -> {} ({} days idle)",
+                t.membership_id,
+                t.from.as_str(),
+                t.to.as_str(),
+                t.idle_days
+            )
+        });
+        if let Some(url) = self.lifecycle_webhook.clone() {
+            let events: Vec<LifecycleEvent> = changes
+                .iter()
+                .filter_map(|t| {
+                    self.id2member
+                        .get(&t.membership_id)
+                        .map(|m| LifecycleEvent {
+                            domain: m.domain.clone(),
+                            name: m.name.clone(),
+                            from: t.from,
+                            to: t.to,
+                            idle_days: t.idle_days,
+                            last_active_at: t.last_active_at,
+                            at: now,
+                        })
+                })
+                .collect();
+            tokio::spawn(async move { notify(&url, &events).await });
+        }
+    }
+
+    async fn refresh_levels(&self, today: NaiveDate) {
+        match LevelTable::load(self.db_pool.get().unwrap(), today) {
+            Ok(table) => *self.levels.write().await = Arc::new(table),
+            Err(e) => error!("load levels {}: {}", today, e),
         }
-        tend
     }
 
     fn get_domain_from_referrer(headers: &HeaderMap) -> Result<String, anyhow::Error> {
@@ -94,17 +338,23 @@ impl Context {
             return Err(anyhow!("referrer header doesn't contains a valid domain"));
         }
 
-        return Ok(referrer_url.domain().unwrap().to_string());
+        Ok(referrer_url.domain().unwrap().to_string())
     }
 
+    // signed：请求带有该成员的嵌入令牌，没有 Referer 时（如 GitHub 的图片代理）按来自成员站点计数
     pub async fn boring_visitor(
         &self,
         v_type: Option<VisitorType>,
         query_member_domain: &str,
         headers: &HeaderMap,
+        signed: bool,
     ) -> Result<(Membership, i64, i64, i64), anyhow::Error> {
         let mut member_domain = query_member_domain.to_string();
-        let domain_referrer = Self::get_domain_from_referrer(headers).unwrap_or("".to_string());
+        let mut domain_referrer = Self::get_domain_from_referrer(headers).unwrap_or("".to_string());
+        if signed && domain_referrer.is_empty() && v_type.is_some_and(|v| v != VisitorType::Referer)
+        {
+            domain_referrer = member_domain.clone();
+        }
         if v_type.is_some_and(|v| v == VisitorType::Referer) {
             if domain_referrer.eq(&*SYSTEM_DOMAIN) {
                 return Err(anyhow!("system domain"));
@@ -127,9 +377,43 @@ impl Context {
                 .unwrap();
             info!("country {}", country);
 
+            // 没有 CF-Connecting-IP 时无法区分网段，只记国家，质量评分按原值计入
+            let mut sources = vec![(DIMENSION_COUNTRY, country_code(country))];
+            if let Some(network) = network_of(ip) {
+                sources.push((DIMENSION_NETWORK, network));
+                sources.push((
+                    DIMENSION_USER_AGENT,
+                    user_agent_digest(
+                        headers
+                            .get("User-Agent")
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or_default(),
+                    ),
+                ));
+            }
+
+            // 先跨天再查访问打点，新一天的首次访问不会被清理掉
+            let now = self.clock.now();
+            let mut counters = self.counters.write().await;
+            self.roll_over(&mut counters, now.date()).await;
+
             let visitor_key = format!("{}_{}_{:?}", ip, id, v_type);
             let mut visitor_cache = self.cache.get(&visitor_key).await;
 
+            // 徽章、卡片、图标嵌在其他成员的页面上，按访客去重后记为引流
+            if visitor_cache.is_none() && v_type.is_some_and(|v| v != VisitorType::Referer) {
+                if let Some(source) = self.domain2id.get(&domain_referrer) {
+                    if source != id {
+                        counters.record_referral(*source, *id, v_type.unwrap() as i32);
+                    }
+                }
+            }
+            if v_type.is_some_and(|v| v == VisitorType::Referer) && !ip.is_empty() {
+                self.arrivals
+                    .set(ip.to_string(), *id, Some(ARRIVAL_TTL))
+                    .await;
+            }
+
             if visitor_cache.is_none() {
                 self.cache
                     .set(visitor_key, (), Some(Duration::from_secs(60 * 60 * 4)))
@@ -141,37 +425,32 @@ impl Context {
 
             let mut notification = false;
 
-            let mut referrer = self.referrer.write().await;
-            let mut dist_r = referrer
-                .get(id)
-                .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
-                .to_owned();
+            let mut dist_r = counters.referrer_of(id);
             if v_type.is_some_and(|v| v == VisitorType::Referer) {
                 if visitor_cache.is_none() {
                     dist_r.0 += 1;
-                    dist_r.1 = now_shanghai();
-                    referrer.insert(*id, dist_r);
+                    dist_r.1 = now;
+                    counters.referrer.insert(*id, dist_r);
+                    counters.pending.entry(*id).or_default().1 += 1;
+                    counters.record_source(*id, VisitorType::Referer as i32, &sources);
                 }
                 notification = true;
             }
-            drop(referrer);
 
-            let mut uv = self.unique_visitor.write().await;
-            let mut dist_uv = uv
-                .get(id)
-                .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
-                .to_owned();
+            let mut dist_uv = counters.unique_visitor_of(id);
             if v_type.is_some_and(|v| v != VisitorType::Referer) {
                 if visitor_cache.is_none() {
                     dist_uv.0 += 1;
-                    dist_uv.1 = now_shanghai();
-                    uv.insert(*id, dist_uv);
+                    dist_uv.1 = now;
+                    counters.unique_visitor.insert(*id, dist_uv);
+                    counters.pending.entry(*id).or_default().0 += 1;
+                    counters.record_source(*id, v_type.unwrap() as i32, &sources);
                 }
                 notification = true;
             }
-            drop(uv);
+            drop(counters);
 
-            let tend = self.get_tend_from_uv_and_rv(dist_uv.0, dist_r.0).await;
+            let tend = self.level_of(id).await;
 
             if notification {
                 let mut member = self.id2member.get(id).unwrap().to_owned();
@@ -204,15 +483,68 @@ impl Context {
         Err(anyhow!("not a member"))
     }
 
+    // 当前计数：独立访客、referrer、等级，不计入访问
+    pub async fn counts_of(&self, id: &i64) -> CounterState {
+        let now = self.clock.now();
+        let mut counters = self.counters.write().await;
+        self.roll_over(&mut counters, now.date()).await;
+        let counts = (counters.unique_visitor_of(id).0, counters.referrer_of(id).0);
+        drop(counters);
+        (counts.0, counts.1, self.level_of(id).await)
+    }
+
+    // 访客从联盟页面点击前往 target_domain：之前从某个成员站点来到联盟时，记为该成员给 target 的引流
+    pub async fn click_through(
+        &self,
+        target_domain: &str,
+        headers: &HeaderMap,
+    ) -> Result<Membership, anyhow::Error> {
+        let target = *self
+            .domain2id
+            .get(target_domain)
+            .ok_or_else(|| anyhow!("not a member"))?;
+        let ip = headers
+            .get("CF-Connecting-IP")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if ip.is_empty() {
+            return Ok(self.id2member.get(&target).unwrap().clone());
+        }
+        if let Some(source) = self.arrivals.get(&ip.to_string()).await {
+            let click_key = format!("{}_{}_{}_click", ip, source, target);
+            if source != target && self.cache.get(&click_key).await.is_none() {
+                self.cache
+                    .set(click_key, (), Some(Duration::from_secs(60 * 60 * 4)))
+                    .await;
+                let now = self.clock.now();
+                let mut counters = self.counters.write().await;
+                self.roll_over(&mut counters, now.date()).await;
+                counters.record_referral(source, target, VisitorType::Referer as i32);
+            }
+        }
+        Ok(self.id2member.get(&target).unwrap().clone())
+    }
+
     pub async fn default(db_pool: DbPool) -> Context {
-        let statistics = Statistics::today(db_pool.get().unwrap()).unwrap_or_default();
+        Self::with_clock(db_pool, Arc::new(SystemClock)).await
+    }
 
-        let mut page_view: HashMap<i64, (i64, NaiveDateTime)> = HashMap::new();
-        let mut referrer: HashMap<i64, (i64, NaiveDateTime)> = HashMap::new();
+    pub async fn with_clock(db_pool: DbPool, clock: Arc<dyn Clock>) -> Context {
+        let today = clock.today();
+        let statistics = Statistics::between(
+            db_pool.get().unwrap(),
+            None,
+            start_of_day(today),
+            start_of_day(today),
+        )
+        .unwrap_or_default();
 
+        let mut counters = DailyCounters::new(today);
         statistics.iter().for_each(|s| {
-            page_view.insert(s.membership_id, (s.unique_visitor, s.updated_at));
-            referrer.insert(
+            counters
+                .unique_visitor
+                .insert(s.membership_id, (s.unique_visitor, s.updated_at));
+            counters.referrer.insert(
                 s.membership_id,
                 (
                     s.referrer,
@@ -222,43 +554,47 @@ impl Context {
             );
         });
 
-        let mut membership: HashMap<i64, Membership> =
-            serde_json::from_str(&fs::read_to_string("./resources/membership.json").unwrap())
-                .unwrap();
-        membership.retain(|_, v| v.hidden.is_none() || !v.hidden.unwrap());
+        let mut membership = Membership::load_all(MEMBERSHIP_PATH).unwrap();
+        membership.retain(|_, v| !v.is_hidden());
 
         let mut domain2id: HashMap<String, i64> = HashMap::new();
-        membership.iter_mut().for_each(|(k, v)| {
-            v.id = *k; // 将 ID 补给 member
+        membership.iter().for_each(|(k, v)| {
             domain2id.insert(v.domain.clone(), *k);
         });
 
-        let rank = Statistics::rank_between(
-            db_pool.get().unwrap(),
-            NaiveDateTime::from_timestamp(0, 0),
-            now_shanghai(),
-        )
-        .unwrap();
+        let avatars = AvatarStore::load(AVATAR_DIR, &membership);
 
-        let monthly_rank = Statistics::rank_between(
-            db_pool.get().unwrap(),
-            now_shanghai() - chrono::Duration::days(30),
-            now_shanghai(),
-        )
-        .unwrap();
+        let rank_cache = RankCache::new();
+        rank_cache
+            .refresh(&db_pool, clock.now(), &WARM_RANK_PERIODS)
+            .await;
 
         let (visitor_tx, visitor_rx) = watch::channel::<String>("".to_string());
 
-        let rank_svg = Statistics::prev_day_rank_avg(db_pool.get().unwrap());
+        let levels = LevelTable::load(db_pool.get().unwrap(), today)
+            .unwrap_or_else(|_| LevelTable::build(today, [].iter()));
+
+        let lifecycle = LifecycleTransition::current_states(&mut db_pool.get().unwrap())
+            .unwrap_or_else(|e| {
+                error!("load lifecycle: {}", e);
+                HashMap::new()
+            });
 
         Context {
             db_pool,
+            clock,
 
-            unique_visitor: RwLock::new(page_view),
-            referrer: RwLock::new(referrer),
-            rank_avg: RwLock::new(rank_svg),
-            rank: RwLock::new(rank),
-            monthly_rank: RwLock::new(monthly_rank),
+            counters: RwLock::new(counters),
+            closed_days: Mutex::new(Vec::new()),
+            levels: RwLock::new(Arc::new(levels)),
+            rank_cache,
+            rank_strategies: RankStrategies::from_env(),
+            prev_positions: RwLock::new(HashMap::new()),
+            lifecycle: RwLock::new(Arc::new(lifecycle)),
+            lifecycle_thresholds: LifecycleThresholds::from_env(),
+            lifecycle_webhook: std::env::var("LIFECYCLE_WEBHOOK_URL")
+                .ok()
+                .filter(|v| !v.is_empty()),
 
             domain2id,
             id2member: membership,
@@ -267,100 +603,479 @@ impl Context {
             visitor_tx,
 
             cache: r_cache::cache::Cache::new(Some(Duration::from_secs(60 * 10))),
+            arrivals: r_cache::cache::Cache::new(Some(Duration::from_secs(60 * 10))),
+            png_cache: PngCache::new(),
+            sparklines: SparklineCache::new(),
+            heatmaps: HeatmapCache::new(),
+            charts: ChartCache::new(),
+            avatars,
         }
     }
 
-    // 每五分钟存一次，发现隔天刷新
-    pub async fn save_per_5_minutes(&self) {
-        let mut uv_cache: HashMap<i64, (i64, NaiveDateTime)> = HashMap::new();
-        let mut referrer_cache: HashMap<i64, (i64, NaiveDateTime)> = HashMap::new();
-        let mut changed_list: Vec<i64> = Vec::new();
-        let mut _today = NaiveDateTime::new(now_shanghai().date(), NaiveTime::from_hms(0, 0, 0));
-        let id_list = Vec::from_iter(self.id2member.keys());
-        loop {
-            tokio::time::sleep(Duration::from_secs(60 * 5)).await;
-            changed_list.clear();
-            // 对比是否有数据更新
-            let mut uv_write = self.unique_visitor.write().await;
-            let mut referrer_write = self.referrer.write().await;
-            id_list.iter().for_each(|id| {
-                let uv = *uv_cache
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                let new_uv = *uv_write
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                if uv.0.ne(&new_uv.0) {
-                    uv_cache.insert(**id, new_uv);
-                    changed_list.push(**id);
-                }
-                let referrer = *referrer_cache
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                let new_referrer = *referrer_write
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                if referrer.0.ne(&new_referrer.0) {
-                    referrer_cache.insert(**id, new_referrer);
-                    if !changed_list.contains(id) {
-                        changed_list.push(**id);
+    // 跨天时在持有写锁的情况下换出旧计数，之后的访问都记到新的一天
+    async fn roll_over(&self, counters: &mut DailyCounters, today: NaiveDate) {
+        if counters.day >= today {
+            return;
+        }
+        let closed = mem::replace(counters, DailyCounters::new(today));
+        self.closed_days.lock().await.push(closed);
+        // 重置访问打点
+        self.cache.clear().await;
+        // 卡片上的趋势图按日期取点，跨天后缓存的 PNG 即使计数相同也已过期
+        self.png_cache.clear();
+    }
+
+    // 写库：把各日期的增量累加到数据库，再读回当天合计
+    pub async fn flush(&self) {
+        let closed: Vec<DailyCounters> = mem::take(&mut *self.closed_days.lock().await);
+        let mut rows: Vec<Statistics> = closed.iter().flat_map(|d| d.increments()).collect();
+
+        let mut counters = self.counters.write().await;
+        let day = counters.day;
+        let current = mem::take(&mut counters.pending);
+        let current_diversity = mem::take(&mut counters.diversity);
+        let current_referrals = mem::take(&mut counters.referrals);
+        rows.extend(
+            current
+                .iter()
+                .map(|(id, delta)| counters.increment_of(id, delta)),
+        );
+        drop(counters);
+
+        let res = self
+            .db_pool
+            .get()
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|mut conn| {
+                Ok(conn.transaction(|conn| {
+                    Statistics::increment_all(conn, &rows)?;
+                    for d in closed.iter() {
+                        VisitDiversity::increment_all(conn, start_of_day(d.day), &d.diversity)?;
+                        ReferralEdge::increment_all(conn, start_of_day(d.day), &d.referrals)?;
                     }
+                    VisitDiversity::increment_all(conn, start_of_day(day), &current_diversity)?;
+                    ReferralEdge::increment_all(conn, start_of_day(day), &current_referrals)
+                })?)
+            });
+        if let Err(e) = res {
+            error!("flush statistics: {}", e);
+            // 写库失败时把增量放回，下次重试
+            self.closed_days.lock().await.extend(closed);
+            let mut counters = self.counters.write().await;
+            if counters.day == day {
+                current.into_iter().for_each(|(id, delta)| {
+                    let p = counters.pending.entry(id).or_default();
+                    p.0 += delta.0;
+                    p.1 += delta.1;
+                });
+                current_diversity.into_iter().for_each(|(key, delta)| {
+                    *counters.diversity.entry(key).or_default() += delta;
+                });
+                current_referrals.into_iter().for_each(|(key, delta)| {
+                    *counters.referrals.entry(key).or_default() += delta;
+                });
+            }
+            return;
+        }
+
+        if let Ok(stored) = Statistics::between(
+            self.db_pool.get().unwrap(),
+            None,
+            start_of_day(day),
+            start_of_day(day),
+        ) {
+            let mut counters = self.counters.write().await;
+            if counters.day == day {
+                counters.rebase(&stored);
+            }
+        }
+
+        if !closed.is_empty() {
+            // 昨天的数据写完后更新等级
+            self.refresh_levels(self.clock.today()).await;
+
+            // 本实例的增量写完后重新记录收盘名次，最后写库的实例得到完整结果
+            for d in closed {
+                if let Err(e) = self.snapshot_ranks(d.day).await {
+                    error!("snapshot ranks {}: {}", d.day, e);
                 }
+            }
+        }
+    }
+
+    // 榜单收录规则：仍在册且未被暂停；排行榜页与随机收录 30 天内活跃的成员，其余收录区间内有访问的成员
+    pub fn is_listed(
+        &self,
+        list: &str,
+        s: &Statistics,
+        now: NaiveDateTime,
+        states: &LifecycleStates,
+    ) -> bool {
+        if !self.id2member.contains_key(&s.membership_id)
+            || !state_of(states, &s.membership_id).listed()
+        {
+            return false;
+        }
+        match list {
+            "all" | "random" => s.updated_at > now - chrono::Duration::days(30),
+            _ => s.unique_visitor + s.referrer > 0,
+        }
+    }
+
+    // days 为榜单的日期闭区间，用于读取该区间的访问质量
+    pub async fn rank_context(
+        &self,
+        now: NaiveDateTime,
+        days: (NaiveDate, NaiveDate),
+    ) -> RankContext {
+        let quality = self
+            .rank_cache
+            .quality(&self.db_pool, days)
+            .await
+            .unwrap_or_else(|e| {
+                error!("load quality {:?}: {}", days, e);
+                Arc::new(HashMap::new())
             });
-            // 更新到数据库
-            changed_list.iter().for_each(|id| {
-                let id_uv = *uv_cache
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                let id_referrer = *referrer_cache
-                    .get(id)
-                    .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-                Statistics::insert_or_update(
-                    self.db_pool.get().unwrap(),
-                    &Statistics {
-                        created_at: _today,
-                        membership_id: *id,
-                        unique_visitor: id_uv.0,
-                        updated_at: id_uv.1,
-                        referrer: id_referrer.0,
-                        latest_referrer_at: Some(id_referrer.1),
-                        id: 0,
-                    },
+        RankContext {
+            now,
+            quality,
+            seed: self.rank_strategies.rotating_seed(now),
+        }
+    }
+
+    // 按榜单的收录规则过滤，再用该榜单的策略排序
+    pub async fn rank_list(
+        &self,
+        list: &str,
+        entries: impl IntoIterator<Item = Statistics>,
+        rank_ctx: &RankContext,
+    ) -> Vec<Statistics> {
+        let states = self.lifecycle_states().await;
+        let mut entries: Vec<Statistics> = entries
+            .into_iter()
+            .filter(|s| self.is_listed(list, s, rank_ctx.now, &states))
+            .collect();
+        self.rank_strategies
+            .for_list(list)
+            .rank(&mut entries, rank_ctx);
+        entries
+    }
+
+    // 记录 day 当天 24:00 时各榜单的名次
+    pub async fn snapshot_ranks(&self, day: NaiveDate) -> Result<(), anyhow::Error> {
+        let created_at = start_of_day(day);
+        let end = created_at + chrono::Duration::days(1) - chrono::Duration::seconds(1);
+        for list in SNAPSHOT_LISTS {
+            let (entries, days) = match RankPeriod::parse(list, None, None)? {
+                Some(period) => {
+                    let (start, last) = period.days(day);
+                    let entries = Statistics::rank_between(
+                        self.db_pool.get()?,
+                        start_of_day(start),
+                        start_of_day(last),
+                    )?;
+                    (entries, (start, last))
+                }
+                None => (
+                    Statistics::between(self.db_pool.get()?, None, created_at, created_at)?,
+                    (day, day),
+                ),
+            };
+            let ranked: Vec<i64> = self
+                .rank_list(list, entries, &self.rank_context(end, days).await)
+                .await
+                .iter()
+                .map(|s| s.membership_id)
+                .collect();
+            RankHistory::record(&mut *self.db_pool.get()?, list, created_at, &ranked)?;
+        }
+        info!("rank snapshot {}", day);
+        self.prev_positions.write().await.clear();
+        Ok(())
+    }
+
+    // 与上一次快照相比的名次变化；该榜单没有快照时为空
+    pub async fn movements(&self, list: &str, ranked: &[Statistics]) -> HashMap<i64, RankMovement> {
+        if !SNAPSHOT_LISTS.contains(&list) {
+            return HashMap::new();
+        }
+        let cached = self.prev_positions.read().await.get(list).cloned();
+        let prev = match cached {
+            Some(prev) => prev,
+            None => {
+                let today = start_of_day(self.clock.today());
+                let prev = self
+                    .db_pool
+                    .get()
+                    .map_err(|e| anyhow!("{}", e))
+                    .and_then(|mut conn| RankHistory::latest_before(&mut conn, list, today));
+                let prev = Arc::new(prev.unwrap_or_else(|e| {
+                    error!("load rank history {}: {}", list, e);
+                    HashMap::new()
+                }));
+                self.prev_positions
+                    .write()
+                    .await
+                    .insert(list.to_string(), prev.clone());
+                prev
+            }
+        };
+        if prev.is_empty() {
+            return HashMap::new();
+        }
+        ranked
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                (
+                    s.membership_id,
+                    RankMovement::of(prev.get(&s.membership_id).cloned(), i as i32 + 1),
                 )
-                .unwrap();
-            });
-            let new_day = NaiveDateTime::new(now_shanghai().date(), NaiveTime::from_hms(0, 0, 0));
-            if new_day.ne(&_today) {
-                _today = new_day;
-                // 如果是跨天重置数据
-                uv_write.clear();
-                referrer_write.clear();
-                uv_cache.clear();
-                referrer_cache.clear();
-                // 重置访问打点
-                self.cache.clear().await;
-                // 更新上日访问量均值
-                let mut rank_avg = self.rank_avg.write().await;
-                *rank_avg = Statistics::prev_day_rank_avg(self.db_pool.get().unwrap());
+            })
+            .collect()
+    }
+
+    // 定时任务：每五分钟写库并刷新排行，在统计时区的 00:00 准时跨天
+    pub async fn run_scheduler(&self) {
+        loop {
+            let now = self.clock.now();
+            let wakeup = next_wakeup(now, FLUSH_INTERVAL_MINUTES);
+            let wait = (wakeup - now).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            self.tick().await;
+        }
+    }
+
+    pub async fn tick(&self) {
+        let now = self.clock.now();
+        let mut counters = self.counters.write().await;
+        self.roll_over(&mut counters, now.date()).await;
+        drop(counters);
+
+        self.flush().await;
+
+        self.rank_cache
+            .refresh(&self.db_pool, now, &WARM_RANK_PERIODS)
+            .await;
+
+        if self.levels.read().await.day != now.date() {
+            self.refresh_levels(now.date()).await;
+        }
+
+        self.refresh_lifecycle(now).await;
+
+        if self.avatars.refresh(&self.id2member) {
+            self.png_cache.clear();
+        }
+
+        // 补记停机期间错过的昨日名次
+        let yesterday = now.date() - chrono::Duration::days(1);
+        let missing = self
+            .db_pool
+            .get()
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|mut conn| RankHistory::exists(&mut conn, start_of_day(yesterday)))
+            .map(|exists| !exists)
+            .unwrap_or(false);
+        if missing {
+            if let Err(e) = self.snapshot_ranks(yesterday).await {
+                error!("snapshot ranks {}: {}", yesterday, e);
             }
-            drop(uv_write);
-            drop(referrer_write);
-
-            let mut rank = self.rank.write().await;
-            *rank = Statistics::rank_between(
-                self.db_pool.get().unwrap(),
-                NaiveDateTime::from_timestamp(0, 0),
-                now_shanghai(),
-            )
+        } else {
+            self.prev_positions.write().await.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::test_db::TestDb;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .and_then(|d| d.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    // 从成员自己的站点打开徽章，计为一个独立访客
+    fn badge_headers(domain: &str, ip: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Referer",
+            HeaderValue::from_str(&format!("https://{}/", domain)).unwrap(),
+        );
+        headers.insert("CF-Connecting-IP", HeaderValue::from_str(ip).unwrap());
+        headers
+    }
+
+    // 编号最小的普通成员，联盟自身的域名不计 referrer
+    fn first_member(ctx: &Context) -> (i64, String) {
+        let id = *ctx
+            .id2member
+            .iter()
+            .filter(|(_, m)| m.domain != *SYSTEM_DOMAIN)
+            .map(|(id, _)| id)
+            .min()
             .unwrap();
+        (id, ctx.id2member[&id].domain.clone())
+    }
 
-            let mut monthly_rank = self.monthly_rank.write().await;
-            *monthly_rank = Statistics::rank_between(
-                self.db_pool.get().unwrap(),
-                now_shanghai() - chrono::Duration::days(30),
-                now_shanghai(),
-            )
+    // 数据库中某成员某天的 (uv, rv)
+    fn stored(pool: &DbPool, id: i64, day: NaiveDate) -> (i64, i64) {
+        Statistics::between(
+            pool.get().unwrap(),
+            Some(id),
+            start_of_day(day),
+            start_of_day(day),
+        )
+        .unwrap()
+        .first()
+        .map(|s| (s.unique_visitor, s.referrer))
+        .unwrap_or_default()
+    }
+
+    async fn visit(ctx: &Context, domain: &str, ip: &str) {
+        visit_as(ctx, VisitorType::Badge, domain, ip).await;
+    }
+
+    async fn visit_as(ctx: &Context, v_type: VisitorType, domain: &str, ip: &str) {
+        ctx.boring_visitor(Some(v_type), domain, &badge_headers(domain, ip), false)
+            .await
             .unwrap();
+    }
+
+    // 23:59 的访问记在当天，00:00 之后同一访客重新计数并记在次日
+    #[tokio::test]
+    async fn visits_roll_over_at_midnight() {
+        let db = TestDb::new();
+        let clock = Arc::new(ManualClock::new(at(18, 23, 59)));
+        let ctx = Context::with_clock(db.pool.clone(), clock.clone()).await;
+        let (id, domain) = first_member(&ctx);
+
+        visit(&ctx, &domain, "1.1.1.1").await;
+        visit(&ctx, &domain, "1.1.1.1").await;
+        assert_eq!(ctx.counts_of(&id).await.0, 1);
+
+        clock.advance(chrono::Duration::minutes(1));
+        visit(&ctx, &domain, "1.1.1.1").await;
+        assert_eq!(ctx.counters.read().await.day, clock.today());
+        assert_eq!(ctx.counts_of(&id).await.0, 1);
+
+        ctx.flush().await;
+        assert_eq!(stored(&db.pool, id, at(18, 0, 0).date()), (1, 0));
+        assert_eq!(stored(&db.pool, id, at(19, 0, 0).date()), (1, 0));
+    }
+
+    // 跨天后没有访问时，定时任务在 00:00 换出并写入前一天的计数
+    #[tokio::test]
+    async fn tick_flushes_previous_day_at_midnight() {
+        let db = TestDb::new();
+        let clock = Arc::new(ManualClock::new(at(18, 23, 58)));
+        let ctx = Context::with_clock(db.pool.clone(), clock.clone()).await;
+        let (id, domain) = first_member(&ctx);
+
+        visit(&ctx, &domain, "1.1.1.1").await;
+        visit(&ctx, &domain, "2.2.2.2").await;
+
+        clock.set(next_wakeup(clock.now(), FLUSH_INTERVAL_MINUTES));
+        assert_eq!(clock.now(), at(19, 0, 0));
+        ctx.tick().await;
+
+        assert_eq!(ctx.counters.read().await.day, at(19, 0, 0).date());
+        assert_eq!(stored(&db.pool, id, at(18, 0, 0).date()), (2, 0));
+        assert_eq!(stored(&db.pool, id, at(19, 0, 0).date()), (0, 0));
+        assert_eq!(ctx.counts_of(&id).await.0, 0);
+        // 等级在跨天写库后按新的一天重新计算
+        assert_eq!(ctx.levels.read().await.day, at(19, 0, 0).date());
+    }
+
+    // 卡片趋势图按日期取点，跨天后计数相同也要重新渲染
+    #[tokio::test]
+    async fn png_cache_expires_at_midnight() {
+        use crate::i18n::Lang;
+        use crate::png_render::PngKey;
+        use crate::svg_template::{Theme, ThemeMode};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let db = TestDb::new();
+        let clock = Arc::new(ManualClock::new(at(18, 23, 59)));
+        let ctx = Context::with_clock(db.pool.clone(), clock.clone()).await;
+        let (id, _) = first_member(&ctx);
+        let theme = Theme {
+            mode: ThemeMode::Light,
+            accent: "#000000".to_string(),
+        };
+        let renders = AtomicUsize::new(0);
+        let render = || async {
+            ctx.png_cache
+                .get_or_render(
+                    PngKey::new("card", id, 1, &theme, Lang::Zh),
+                    (0, 0, 1),
+                    || {
+                        renders.fetch_add(1, Ordering::SeqCst);
+                        Ok(
+                            r#"<svg xmlns="http://www.w3.org/2000/svg" width="1" height="1"/>"#
+                                .to_string(),
+                        )
+                    },
+                )
+                .await
+                .unwrap()
+        };
+
+        render().await;
+        render().await;
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+        clock.advance(chrono::Duration::minutes(1));
+        ctx.tick().await;
+        render().await;
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+    }
+
+    // 两个 Context 各用一个连接池打开同一个 SQLite 文件，各自计数、同时写库，合计不丢失。
+    // 只在同一进程内、共用一个时钟模拟多实例，不覆盖跨进程的文件锁与各实例时钟不一致的情况
+    #[tokio::test]
+    async fn contexts_sharing_database_file_sum_their_counts() {
+        let db = TestDb::new();
+        let clock = Arc::new(ManualClock::new(at(18, 12, 0)));
+        let a = Context::with_clock(db.pool.clone(), clock.clone()).await;
+        let b = Context::with_clock(TestDb::open(&db.path), clock.clone()).await;
+        let (id, domain) = first_member(&a);
+
+        for round in 0..3 {
+            for i in 0..4 {
+                visit(&a, &domain, &format!("10.0.{}.{}", round, i)).await;
+                visit_as(
+                    &a,
+                    VisitorType::Referer,
+                    &domain,
+                    &format!("10.1.{}.{}", round, i),
+                )
+                .await;
+            }
+            for i in 0..3 {
+                visit(&b, &domain, &format!("10.2.{}.{}", round, i)).await;
+                visit_as(
+                    &b,
+                    VisitorType::Referer,
+                    &domain,
+                    &format!("10.3.{}.{}", round, i),
+                )
+                .await;
+            }
+            tokio::join!(a.flush(), b.flush());
+        }
+        // 写库后计数仍保留在内存，再次写库不会重复累加
+        tokio::join!(a.flush(), b.flush());
+
+        assert_eq!(stored(&db.pool, id, clock.today()), (3 * 7, 3 * 7));
+        // 写库后各实例以数据库中的合计为准
+        for ctx in [&a, &b] {
+            let (uv, rv, _) = ctx.counts_of(&id).await;
+            assert_eq!((uv, rv), (3 * 7, 3 * 7));
         }
     }
 }
@@ -1,58 +1,126 @@
-use std::{collections::HashMap, io::Read, sync::Arc, time::Duration};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use askama::Template;
 use axum::{
+    body::Bytes,
     extract::{
         ws::{Message, WebSocket},
         Extension, Path, Query, WebSocketUpgrade,
     },
-    http::{header::HeaderMap, StatusCode},
-    response::{Html, IntoResponse, Response},
+    http::{
+        header::{HeaderMap, CACHE_CONTROL},
+        HeaderValue, StatusCode,
+    },
+    response::{Html, IntoResponse, Redirect, Response},
 };
-use base64::{engine::general_purpose::STANDARD, Engine};
-use chrono::NaiveDateTime;
-use lazy_static::lazy_static;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
 use tokio::select;
+use tracing::error;
 
 use crate::{
+    api_router::{parse_date, referral_graph, ReferralGraph},
     app_model::{Context, DynContext, VisitorType},
-    membership_model::{Membership, RankAndMembership},
-    now_shanghai,
+    chart::{Chart, ChartKey, ChartRange, ChartSeries},
+    clock::start_of_day,
+    embed::{self, EmbedSnippets},
+    heatmap::{Heatmap, HeatmapRange},
+    i18n::Lang,
+    lifecycle_model::{state_of, LifecycleState},
+    membership_model::RankAndMembership,
+    png_render::{split_png, CounterState, PngKey},
+    rank_cache::RankPeriod,
+    rank_listing::{list_members, ListedMember, ListingQuery, SORT_KEYS},
+    sparkline::{Sparkline, TrendRange},
     statistics_model::Statistics,
+    svg_template::{
+        BadgeLayout, BadgeSvg, CardSvg, ChartSvg, HeatmapSvg, IconSvg, Theme, ThemeQuery,
+    },
     GIT_HASH,
 };
 
-lazy_static! {
-    static ref BADGE_CONTENT: String = {
-        let mut s = String::new();
-        std::fs::File::open("templates/assets/img/badge.svg")
-            .unwrap()
-            .read_to_string(&mut s)
-            .unwrap();
-        s
-    };
-    static ref CARD_CONTENT: String = {
-        let mut s = String::new();
-        std::fs::File::open("templates/assets/img/card.svg")
-            .unwrap()
-            .read_to_string(&mut s)
-            .unwrap();
-        s
-    };
-    static ref ICON_CONTENT: String = {
-        let mut s = String::new();
-        std::fs::File::open("templates/assets/img/logo.svg")
-            .unwrap()
-            .read_to_string(&mut s)
-            .unwrap();
-        s
-    };
-    static ref CARD_DOMAIN_UNICODE_WIDTH: usize =
-        unicode_width::UnicodeWidthStr::width("bigtoyscompany");
-    static ref BADGE_DOMAIN_UNICODE_WIDTH: usize =
-        unicode_width::UnicodeWidthStr::width("bigtoyscompa");
+// 主题、语言、样式参数有误时返回 400，此时不计入访问
+fn bad_query(e: String) -> Response {
+    (StatusCode::BAD_REQUEST, [("content-type", "text/plain")], e).into_response()
+}
+
+fn not_member() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        [("content-type", "text/plain")],
+        "not a member",
+    )
+        .into_response()
+}
+
+// 渲染失败时返回 500；内容随 Accept-Language 变化
+fn svg_response<T: Template>(tpl: T) -> Response {
+    match tpl.render() {
+        Ok(svg) => (
+            StatusCode::OK,
+            [
+                ("content-type", "image/svg+xml"),
+                ("vary", "Accept-Language"),
+            ],
+            svg,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// 统计类图片按写库间隔缓存
+fn cached_svg<T: Template>(tpl: T) -> Response {
+    let mut res = svg_response(tpl);
+    if res.status().is_success() {
+        res.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("public, max-age=300"),
+        );
+    }
+    res
+}
+
+// 没有倍率时输出 SVG，否则输出按计数缓存的 PNG
+async fn image_response<T, F>(
+    ctx: &Context,
+    png: Option<(&'static str, i64, u32)>,
+    state: CounterState,
+    theme: Theme,
+    lang: Lang,
+    tpl: F,
+) -> Response
+where
+    T: Template,
+    F: FnOnce(Theme, Lang) -> T,
+{
+    let Some((kind, member, scale)) = png else {
+        return svg_response(tpl(theme, lang));
+    };
+    let theme = theme.rasterized();
+    let key = PngKey::new(kind, member, scale, &theme, lang);
+    match ctx
+        .png_cache
+        .get_or_render(key, state, || tpl(theme, lang).render())
+        .await
+    {
+        Ok(png) => (
+            StatusCode::OK,
+            [("content-type", "image/png"), ("vary", "Accept-Language")],
+            png,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// 带令牌的图片多经由图片代理访问，要求代理每次回源，访问才能计入
+fn signed_response(mut res: Response, signed: bool) -> Response {
+    if signed && res.status().is_success() {
+        res.headers_mut().insert(
+            CACHE_CONTROL,
+            HeaderValue::from_static("no-cache, max-age=0"),
+        );
+    }
+    res
 }
 
 pub async fn ws_upgrade(
@@ -87,11 +155,26 @@ async fn handle_socket(ctx: Arc<Context>, mut socket: WebSocket) {
 
 pub async fn show_badge(
     Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
     Extension(ctx): Extension<DynContext>,
 ) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let layout = match BadgeLayout::parse(&query) {
+        Ok(layout) => layout,
+        Err(e) => return bad_query(e),
+    };
+    let (domain, scale) = split_png(&domain);
+    let signed = embed::verified(&query, domain);
     let tend = ctx
-        .boring_visitor(Some(VisitorType::Badge), &domain, &headers)
+        .boring_visitor(Some(VisitorType::Badge), domain, &headers, signed)
         .await;
     if tend.is_err() {
         return (
@@ -102,32 +185,41 @@ pub async fn show_badge(
             .into_response();
     }
 
-    let tend = tend.unwrap();
-
-    let domain = tend.0.domain.clone();
-    let domain_unicode_width = unicode_width::UnicodeWidthStr::width(domain.as_str());
-    let font_size = (14 * BADGE_DOMAIN_UNICODE_WIDTH.to_owned() / domain_unicode_width).min(14);
-
-    (
-        StatusCode::OK,
-        [("content-type", "image/svg+xml")],
-        BADGE_CONTENT
-            .replace("domain.fans", &domain)
-            .replace("font-size:14px;", &format!("font-size:{}px;", font_size))
-            .replace("1233", &tend.1.to_string())
-            .replace("1244", &tend.2.to_string())
-            .replace("1255", &tend.3.to_string()),
+    let (member, uv, rv, level) = tend.unwrap();
+    let res = image_response(
+        &ctx,
+        scale.map(|s| (layout.name, member.id, s)),
+        (uv, rv, level),
+        theme.resolve(&member),
+        lang,
+        |theme, lang| BadgeSvg::new(layout, &member, (uv, rv, level), theme, lang),
     )
-        .into_response()
+    .await;
+    signed_response(res, signed)
 }
 
 pub async fn show_card(
     Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
     Extension(ctx): Extension<DynContext>,
 ) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let trend = match TrendRange::parse(&query) {
+        Ok(trend) => trend,
+        Err(e) => return bad_query(e),
+    };
+    let (domain, scale) = split_png(&domain);
+    let signed = embed::verified(&query, domain);
     let tend = ctx
-        .boring_visitor(Some(VisitorType::Card), &domain, &headers)
+        .boring_visitor(Some(VisitorType::Card), domain, &headers, signed)
         .await;
     if tend.is_err() {
         return (
@@ -138,44 +230,62 @@ pub async fn show_card(
             .into_response();
     }
 
-    let tend = tend.unwrap();
-    let domain = tend.0.domain.clone();
-    let domain_unicode_width = unicode_width::UnicodeWidthStr::width(domain.as_str());
-    let font_size = (36 * CARD_DOMAIN_UNICODE_WIDTH.to_owned() / domain_unicode_width).min(36);
-
-    let avatar_img_base64 = match std::fs::read(format!("resources/avatar/{}.png", &tend.0.id)) {
-        Ok(img) => STANDARD.encode(img),
-        Err(_) => {
-            "iVBORw0KGgoAAAANSUhEUgAAAAgAAAAIAQMAAAD+wSzIAAAABlBMVEX///+/v7+jQ3Y5AAAADklEQVQI12P4AIX8EAgALgAD/aNpbtEAAAAASUVORK5CYII".to_string()
+    let (member, uv, rv, level) = tend.unwrap();
+    // 历史读取失败时只画今天
+    let mut points = match ctx
+        .sparklines
+        .history(&ctx.db_pool, member.id, trend, ctx.clock.today())
+    {
+        Ok(history) => history.as_ref().clone(),
+        Err(e) => {
+            error!("load sparkline {}: {}", member.domain, e);
+            Vec::new()
         }
     };
-
-    (
-        StatusCode::OK,
-        [("content-type", "image/svg+xml")],
-        CARD_CONTENT
-            .replace("iVBORw0KGgoAAAANSUhEUgAAAAgAAAAIAQMAAAD+wSzIAAAABlBMVEX///+/v7+jQ3Y5AAAADklEQVQI12P4AIX8EAgALgAD/aNpbtEAAAAASUVORK5CYII", &avatar_img_base64)
-            .replace("熊宝的米表", &tend.0.name)
-            .replace("资深域名玩家，擅长新顶、单字符等。", &tend.0.description)
-            .replace("domain.fans", &domain)
-            .replace("font-size: 36px;", &format!("font-size: {}px;", font_size))
-            .replace("1233", &tend.1.to_string())
-            .replace("1244", &tend.2.to_string())
-            .replace("1255", &tend.3.to_string()),
+    points.push((uv, rv));
+    let res = image_response(
+        &ctx,
+        scale.map(|s| (trend.png_kind(), member.id, s)),
+        (uv, rv, level),
+        theme.resolve(&member),
+        lang,
+        |theme, lang| {
+            CardSvg::new(
+                &member,
+                ctx.avatars.get(&member),
+                (uv, rv, level),
+                Sparkline::build(&points),
+                theme,
+                lang,
+            )
+        },
     )
-        .into_response()
+    .await;
+    signed_response(res, signed)
 }
 
 pub async fn show_favicon(
     Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
     Extension(ctx): Extension<DynContext>,
 ) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let (domain, scale) = split_png(&domain);
+    let signed = embed::verified(&query, domain);
     let tend = ctx
         .boring_visitor(
             Some(crate::app_model::VisitorType::Favicon),
-            &domain,
+            domain,
             &headers,
+            signed,
         )
         .await;
     if tend.is_err() {
@@ -186,31 +296,169 @@ pub async fn show_favicon(
         )
             .into_response();
     }
-    (
-        StatusCode::OK,
-        [("content-type", "image/svg+xml")],
-        ICON_CONTENT.to_string(),
+    let member = tend.unwrap().0;
+    let res = image_response(
+        &ctx,
+        scale.map(|s| ("icon", member.id, s)),
+        (0, 0, 0),
+        theme.resolve(&member),
+        lang,
+        IconSvg::new,
     )
-        .into_response()
+    .await;
+    signed_response(res, signed)
 }
 
 pub async fn show_icon(
     Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
     Extension(ctx): Extension<DynContext>,
 ) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let (domain, scale) = split_png(&domain);
+    let signed = embed::verified(&query, domain);
     let tend = ctx
-        .boring_visitor(Some(crate::app_model::VisitorType::ICON), &domain, &headers)
+        .boring_visitor(
+            Some(crate::app_model::VisitorType::ICON),
+            domain,
+            &headers,
+            signed,
+        )
         .await;
     if tend.is_err() {
         return (StatusCode::NOT_FOUND, tend.err().unwrap().to_string()).into_response();
     }
-    (
-        StatusCode::OK,
-        [("content-type", "image/svg+xml")],
-        ICON_CONTENT.to_string(),
+    let member = tend.unwrap().0;
+    let res = image_response(
+        &ctx,
+        scale.map(|s| ("icon", member.id, s)),
+        (0, 0, 0),
+        theme.resolve(&member),
+        lang,
+        IconSvg::new,
     )
-        .into_response()
+    .await;
+    signed_response(res, signed)
+}
+
+// 日历热力图只读取统计，不计入访问
+pub async fn show_heatmap(
+    Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let today = ctx.clock.today();
+    let range = match HeatmapRange::parse(&query, today) {
+        Ok(range) => range,
+        Err(e) => return bad_query(e),
+    };
+    let id = match ctx.domain2id.get(&domain) {
+        Some(id) => *id,
+        None => return not_member(),
+    };
+    // 历史读取失败时只画今天
+    let mut visits = match ctx.heatmaps.history(&ctx.db_pool, id, range, today) {
+        Ok(history) => history.as_ref().clone(),
+        Err(e) => {
+            error!("load heatmap {}: {}", domain, e);
+            HashMap::new()
+        }
+    };
+    let days = range.days(today);
+    if days.1 == today {
+        let (uv, rv, _) = ctx.counts_of(&id).await;
+        visits.insert(today, uv + rv);
+    }
+    let member = ctx.id2member.get(&id).unwrap();
+    cached_svg(HeatmapSvg::new(
+        member,
+        range,
+        Heatmap::build(days, &visits, lang.text()),
+        theme.resolve(member),
+        lang,
+    ))
+}
+
+// 访问趋势图只读取统计，不计入访问；日期与今天的计数不变时直接返回缓存
+pub async fn show_chart(
+    Path(domain): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let theme = match ThemeQuery::parse(&query) {
+        Ok(theme) => theme,
+        Err(e) => return bad_query(e),
+    };
+    let lang = match Lang::negotiate(&query, &headers) {
+        Ok(lang) => lang,
+        Err(e) => return bad_query(e),
+    };
+    let range = match ChartRange::parse(&query) {
+        Ok(range) => range,
+        Err(e) => return bad_query(e),
+    };
+    let series = match ChartSeries::parse(&query) {
+        Ok(series) => series,
+        Err(e) => return bad_query(e),
+    };
+    let id = match ctx.domain2id.get(&domain) {
+        Some(id) => *id,
+        None => return not_member(),
+    };
+    let member = ctx.id2member.get(&id).unwrap();
+    let today = ctx.clock.today();
+    let (uv, rv, _) = ctx.counts_of(&id).await;
+    let theme = theme.resolve(member);
+    let key = ChartKey::new(id, range, &series, &theme, lang);
+    let render = |history: &[(i64, i64)]| {
+        let mut points = history.to_vec();
+        points.push((uv, rv));
+        let chart = Chart::build(&points, &series, range, today, lang.text());
+        ChartSvg::new(member, chart, theme, lang).render()
+    };
+    // 历史读取失败时只画今天，这张图不进缓存，也不让浏览器与 CDN 缓存
+    let (svg, cache_control) = match ctx.charts.history(&ctx.db_pool, id, range, today) {
+        Ok(history) => (
+            ctx.charts
+                .get_or_render(key, (today, uv, rv), || render(&history)),
+            "public, max-age=300",
+        ),
+        Err(e) => {
+            error!("load chart {}: {}", domain, e);
+            (render(&[]).map(Bytes::from), "no-cache")
+        }
+    };
+    match svg {
+        Ok(svg) => (
+            StatusCode::OK,
+            [
+                ("content-type", "image/svg+xml"),
+                ("vary", "Accept-Language"),
+                ("cache-control", cache_control),
+            ],
+            svg,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
 #[derive(Template)]
@@ -219,138 +467,221 @@ struct HomeTemplate {
     version: String,
     rank: Vec<RankAndMembership>,
     rank_type: String,
+    from: String,
+    to: String,
     level: HashMap<i64, i64>,
+    seed: u64,
+    country: Option<String>,
+    country_links: Vec<CountryLink>,
 }
 
+// 首页按国家筛选的链接
+struct CountryLink {
+    code: String,
+    href: String,
+    current: bool,
+}
+
+// 首页国家筛选最多列出的国家数
+const COUNTRY_LINK_LIMIT: usize = 12;
+
 pub async fn home_page(
     Extension(ctx): Extension<DynContext>,
     Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
-) -> Result<Html<String>, String> {
+) -> Result<Html<String>, (StatusCode, String)> {
     let _ = ctx
-        .boring_visitor(Some(crate::app_model::VisitorType::Referer), "", &headers)
+        .boring_visitor(
+            Some(crate::app_model::VisitorType::Referer),
+            "",
+            &headers,
+            false,
+        )
         .await;
 
     let mut rank_type = query
         .get("rank_type")
         .unwrap_or(&"daily".to_string())
         .clone();
-    if !["daily", "monthly", "random"].contains(&rank_type.as_str()) {
+    // 自定义区间的日期有误时返回 400，不再当作未填写
+    let bad_request = |err: String| (StatusCode::BAD_REQUEST, err);
+    let from = parse_date(&query, "from").map_err(bad_request)?;
+    let to = parse_date(&query, "to").map_err(bad_request)?;
+    let period =
+        RankPeriod::parse(&rank_type, from, to).map_err(|err| bad_request(err.to_string()))?;
+    if period.is_none() && !["daily", "random"].contains(&rank_type.as_str()) {
         rank_type = "daily".to_string();
     }
+    let country = match query
+        .get("country")
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+    {
+        Some(c) if c.len() == 2 && c.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            Some(c.to_ascii_uppercase())
+        }
+        Some(c) => {
+            return Err(bad_request(format!(
+                "invalid country {}, expected a two-letter code",
+                c
+            )))
+        }
+        None => None,
+    };
 
-    let referrer_read = ctx.referrer.read().await;
-    let uv_read = ctx.unique_visitor.read().await;
+    let counters = ctx.counters.read().await;
+    let now = ctx.clock.now();
 
     let mut level: HashMap<i64, i64> = HashMap::new();
-    let mut rank_vec: Vec<(i64, NaiveDateTime, i64)> = Vec::new();
+    let mut rank_daily: Vec<Statistics> = Vec::new();
 
     for k in ctx.id2member.keys() {
-        let uv = uv_read
-            .get(k)
-            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
-            .to_owned();
-        let rv = referrer_read
-            .get(k)
-            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)))
-            .to_owned();
+        let uv = counters.unique_visitor_of(k);
+        let rv = counters.referrer_of(k);
         if uv.0 > 0 || rv.0 > 0 || rank_type == "random" {
-            rank_vec.push((k.to_owned(), rv.1, uv.0));
-            level.insert(k.to_owned(), ctx.get_tend_from_uv_and_rv(uv.0, rv.0).await);
+            rank_daily.push(Statistics {
+                id: 0,
+                created_at: start_of_day(counters.day),
+                updated_at: uv.1,
+                membership_id: k.to_owned(),
+                unique_visitor: uv.0,
+                referrer: rv.0,
+                latest_referrer_at: Some(rv.1),
+            });
+            level.insert(k.to_owned(), ctx.level_of(k).await);
         }
     }
-
-    let rank = match rank_type.as_str() {
-        "daily" => {
-            rank_vec.sort_by(|a, b| match b.1.cmp(&a.1) {
-                std::cmp::Ordering::Equal => b.2.cmp(&a.2),
-                _ => b.1.cmp(&a.1),
-            });
-            let mut rank_daily = Vec::new();
-            for v in rank_vec {
-                if rank_daily.len() >= 30 {
-                    break;
-                }
-                rank_daily.push(RankAndMembership {
-                    rank: Statistics {
-                        id: 0,
-                        created_at: now_shanghai(),
-                        updated_at: now_shanghai(),
-                        membership_id: v.0,
-                        unique_visitor: match uv_read.get(&v.0) {
-                            Some(uv) => uv.0,
-                            None => 0,
-                        },
-                        referrer: match referrer_read.get(&v.0) {
-                            Some(rv) => rv.0,
-                            None => 0,
-                        },
-                        latest_referrer_at: Some(match referrer_read.get(&v.0) {
-                            Some(rv) => rv.1,
-                            None => NaiveDateTime::from_timestamp(0, 0),
-                        }),
-                    },
-                    membership: ctx.id2member.get(&v.0).unwrap().to_owned(),
-                });
+    drop(counters);
+
+    let entries: Vec<Statistics> = match (rank_type.as_str(), period) {
+        (_, Some(period)) => ctx
+            .rank_cache
+            .get(&ctx.db_pool, period, now)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+            .to_vec(),
+        ("random", _) => ctx
+            .rank_cache
+            .get(&ctx.db_pool, RankPeriod::AllTime, now)
+            .await
+            .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+            .to_vec(),
+        _ => rank_daily,
+    };
+    let days = match (rank_type.as_str(), period) {
+        (_, Some(period)) => period.days(now.date()),
+        ("random", _) => RankPeriod::AllTime.days(now.date()),
+        _ => (now.date(), now.date()),
+    };
+    let countries = ctx
+        .rank_cache
+        .countries(&ctx.db_pool, days)
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    // 按国家筛选时只看来自该国家的访问
+    let entries: Vec<Statistics> = match &country {
+        Some(country) => {
+            let counts: HashMap<i64, (i64, i64)> = countries
+                .iter()
+                .filter(|c| &c.country == country)
+                .map(|c| (c.membership_id, (c.unique_visitor, c.referrer)))
+                .collect();
+            entries
+                .into_iter()
+                .filter_map(|mut s| {
+                    counts.get(&s.membership_id).map(|(uv, rv)| {
+                        s.unique_visitor = *uv;
+                        s.referrer = *rv;
+                        s
+                    })
+                })
+                .collect()
+        }
+        None => entries,
+    };
+    let mut totals: HashMap<&str, i64> = HashMap::new();
+    countries.iter().for_each(|c| {
+        *totals.entry(c.country.as_str()).or_default() += c.unique_visitor + c.referrer
+    });
+    let mut codes: Vec<(&str, i64)> = totals.into_iter().collect();
+    codes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let mut codes: Vec<String> = codes
+        .into_iter()
+        .take(COUNTRY_LINK_LIMIT)
+        .map(|(c, _)| c.to_string())
+        .collect();
+    if let Some(country) = &country {
+        if !codes.contains(country) {
+            codes.push(country.clone());
+        }
+    }
+    let country_href = |code: Option<&str>| {
+        let mut s = url::form_urlencoded::Serializer::new(String::new());
+        s.append_pair("rank_type", &rank_type);
+        if let (Some(from), Some(to)) = (from, to) {
+            if rank_type == "custom" {
+                s.append_pair("from", &from.to_string());
+                s.append_pair("to", &to.to_string());
             }
-            rank_daily
         }
-        "monthly" => {
-            let mut rank_monthly = Vec::new();
-            let monthly_rank = ctx.monthly_rank.read().await.to_owned();
-            monthly_rank
-                .iter()
-                .filter(|r| ctx.id2member.contains_key(&r.membership_id))
-                .for_each(|r| {
-                    if rank_monthly.len() >= 30
-                        || r.updated_at < now_shanghai() - chrono::Duration::days(30)
-                    {
-                        return;
-                    }
-                    let m = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
-                    rank_monthly.push(RankAndMembership {
-                        rank: r.to_owned(),
-                        membership: m,
-                    });
-                });
-            rank_monthly
+        if let Some(code) = code {
+            s.append_pair("country", code);
         }
-        "random" => {
-            let mut rank_and_membership_to_be_remove = Vec::new();
-            let mut rank_and_membership = Vec::new();
-            let rank = ctx.rank.read().await.to_owned();
-            rank.iter()
-                .filter(|r| ctx.id2member.contains_key(&r.membership_id))
-                .for_each(|r| {
-                    if r.updated_at > now_shanghai() - chrono::Duration::days(30) {
-                        let m = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
-                        rank_and_membership.push(RankAndMembership {
-                            rank: r.to_owned(),
-                            membership: m,
-                        });
-                    } else {
-                        let m: Membership = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
-                        rank_and_membership_to_be_remove.push(RankAndMembership {
-                            rank: r.to_owned(),
-                            membership: m,
-                        });
-                    }
-                });
-            rank_and_membership.shuffle(&mut thread_rng());
-            rank_and_membership.into_iter().take(30).collect()
+        format!("?{}", s.finish())
+    };
+    let mut country_links = vec![CountryLink {
+        code: "全部地区".to_string(),
+        href: country_href(None),
+        current: country.is_none(),
+    }];
+    country_links.extend(codes.into_iter().map(|code| CountryLink {
+        href: country_href(Some(&code)),
+        current: country.as_ref() == Some(&code),
+        code,
+    }));
+
+    let mut rank_ctx = ctx.rank_context(now, days).await;
+    // ?seed= 复现某一次随机顺序：权重与 30 天收录窗口都按种子所在轮换周期的起点计算，
+    // 不带种子时也一样，同一周期内的顺序与分享出去的链接一致
+    if rank_type == "random" {
+        if let Some(seed) = query.get("seed").and_then(|s| s.parse::<u64>().ok()) {
+            rank_ctx.seed = seed;
         }
-        _ => {
-            vec![]
+        if let Some(seed_time) = ctx.rank_strategies.seed_time(rank_ctx.seed) {
+            rank_ctx.now = seed_time.min(now);
         }
+    }
+    let mut entries = ctx.rank_list(&rank_type, entries, &rank_ctx).await;
+    entries.truncate(30);
+    // 名次快照不区分国家
+    let movements = match country {
+        Some(_) => HashMap::new(),
+        None => ctx.movements(&rank_type, &entries).await,
     };
 
+    let rank = entries
+        .into_iter()
+        .map(|r| RankAndMembership {
+            membership: ctx.id2member.get(&r.membership_id).unwrap().to_owned(),
+            movement: movements.get(&r.membership_id).cloned(),
+            rank: r,
+        })
+        .collect();
+
     let tpl = HomeTemplate {
         rank,
         rank_type,
+        from: from.map(|d| d.to_string()).unwrap_or_default(),
+        to: to.map(|d| d.to_string()).unwrap_or_default(),
         level,
+        seed: rank_ctx.seed,
+        country,
+        country_links,
         version: GIT_HASH[0..8].to_string(),
     };
-    let html = tpl.render().map_err(|err| err.to_string())?;
+    let html = tpl
+        .render()
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
     Ok(Html(html))
 }
 
@@ -358,61 +689,200 @@ pub async fn home_page(
 #[template(path = "join_us.html")]
 struct JoinUsTemplate {
     version: String,
+    // 示例嵌入代码的 JSON，输入域名后由 /api/embed 替换
+    snippets: String,
 }
 
 pub async fn join_us_page() -> Result<Html<String>, String> {
     let tpl = JoinUsTemplate {
         version: GIT_HASH[0..8].to_string(),
+        snippets: serde_json::to_string(&EmbedSnippets::placeholder())
+            .map_err(|err| err.to_string())?,
     };
     let html = tpl.render().map_err(|err| err.to_string())?;
     Ok(Html(html))
 }
 
+// 排序切换链接
+struct SortLink {
+    label: &'static str,
+    href: String,
+    current: bool,
+    desc: bool,
+}
+
+struct PageLink {
+    page: usize,
+    href: String,
+    current: bool,
+}
+
 #[derive(Template)]
 #[template(path = "rank.html")]
 struct RankTemplate {
     version: String,
-    rank: Vec<RankAndMembership>,
+    members: Vec<ListedMember>,
     to_be_remove: Vec<RankAndMembership>,
+    warn_days: i64,
+    query: ListingQuery,
+    total: usize,
+    pages: usize,
+    sort_links: Vec<SortLink>,
+    page_links: Vec<PageLink>,
+    prev_href: Option<String>,
+    next_href: Option<String>,
 }
 
+// 当前页前后各显示的页码数
+const PAGE_LINK_RADIUS: usize = 3;
+
 pub async fn rank_page(
     Extension(ctx): Extension<DynContext>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
-) -> Result<Html<String>, String> {
+) -> Response {
+    let query = match ListingQuery::parse(&query) {
+        Ok(query) => query,
+        Err(e) => return bad_query(e),
+    };
     let _ = ctx
-        .boring_visitor(Some(crate::app_model::VisitorType::Referer), "", &headers)
+        .boring_visitor(
+            Some(crate::app_model::VisitorType::Referer),
+            "",
+            &headers,
+            false,
+        )
         .await;
 
-    let rank = ctx.rank.read().await.to_owned();
-
-    let mut rank_and_membership_to_be_remove = Vec::new();
-
-    let mut rank_and_membership = Vec::new();
-
-    rank.iter()
-        .filter(|r| ctx.id2member.contains_key(&r.membership_id))
-        .for_each(|r| {
-            if r.updated_at > now_shanghai() - chrono::Duration::days(30) {
-                let m = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
-                rank_and_membership.push(RankAndMembership {
-                    rank: r.to_owned(),
-                    membership: m,
-                });
-            } else {
-                let m = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
-                rank_and_membership_to_be_remove.push(RankAndMembership {
-                    rank: r.to_owned(),
-                    membership: m,
-                });
-            }
-        });
+    let listing = match list_members(&ctx, &query).await {
+        Ok(listing) => listing,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    // 30 天内没有访问的成员，只在未搜索的第一页显示
+    let mut to_be_remove = Vec::new();
+    if query.page == 1 && query.q.is_empty() {
+        let now = ctx.clock.now();
+        let all = match ctx
+            .rank_cache
+            .get(&ctx.db_pool, RankPeriod::AllTime, now)
+            .await
+        {
+            Ok(all) => all,
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        };
+        let states = ctx.lifecycle_states().await;
+        to_be_remove = all
+            .iter()
+            .filter(|r| ctx.id2member.contains_key(&r.membership_id))
+            .filter(|r| state_of(&states, &r.membership_id) == LifecycleState::Warned)
+            .cloned()
+            .collect::<Vec<_>>();
+        ctx.rank_strategies.for_list("all").rank(
+            &mut to_be_remove,
+            &ctx.rank_context(now, RankPeriod::AllTime.days(now.date()))
+                .await,
+        );
+    }
+    let to_be_remove = to_be_remove
+        .into_iter()
+        .map(|r| RankAndMembership {
+            membership: ctx.id2member.get(&r.membership_id).unwrap().to_owned(),
+            movement: None,
+            rank: r,
+        })
+        .collect();
+
+    let sort_links = SORT_KEYS
+        .iter()
+        .map(|k| SortLink {
+            label: k.label(),
+            href: format!("/rank{}", query.with_sort(*k).query_string()),
+            current: *k == query.sort,
+            desc: query.desc,
+        })
+        .collect();
+    let first = query.page.saturating_sub(PAGE_LINK_RADIUS).max(1);
+    let last = query
+        .page
+        .saturating_add(PAGE_LINK_RADIUS)
+        .min(listing.pages);
+    let page_links = (first..=last)
+        .map(|p| PageLink {
+            page: p,
+            href: format!("/rank{}", query.with_page(p).query_string()),
+            current: p == query.page,
+        })
+        .collect();
+    let prev_href = (query.page > 1 && query.page <= listing.pages)
+        .then(|| format!("/rank{}", query.with_page(query.page - 1).query_string()));
+    let next_href = (query.page < listing.pages)
+        .then(|| format!("/rank{}", query.with_page(query.page + 1).query_string()));
 
     let tpl = RankTemplate {
-        rank: rank_and_membership,
-        to_be_remove: rank_and_membership_to_be_remove,
+        members: listing.members,
+        to_be_remove,
+        warn_days: ctx.lifecycle_thresholds.warn_days,
+        total: listing.total,
+        pages: listing.pages,
+        query,
+        sort_links,
+        page_links,
+        prev_href,
+        next_href,
         version: GIT_HASH[0..8].to_string(),
     };
-    let html = tpl.render().map_err(|err| err.to_string())?;
-    Ok(Html(html))
+    // 页面会按 Referer 计入访问，不能让 CDN 缓存；只有 /api/rank 按写库间隔公开缓存
+    match tpl.render() {
+        Ok(html) => (
+            [(CACHE_CONTROL, HeaderValue::from_static("private, no-cache"))],
+            Html(html),
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// 联盟页面上的成员链接经由这里跳转，记录点击引流
+pub async fn go_member(
+    Path(domain): Path<String>,
+    headers: HeaderMap,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    match ctx.click_through(&domain, &headers).await {
+        Ok(member) => Redirect::temporary(&format!("https://{}", member.domain)).into_response(),
+        Err(e) => (
+            StatusCode::NOT_FOUND,
+            [("content-type", "text/plain")],
+            e.to_string(),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "referrals.html")]
+struct ReferralsTemplate {
+    version: String,
+    graph: ReferralGraph,
+    domain: String,
+}
+
+pub async fn referrals_page(
+    Query(query): Query<HashMap<String, String>>,
+    Extension(ctx): Extension<DynContext>,
+) -> Response {
+    let graph = match referral_graph(&ctx, &query) {
+        Ok(graph) => graph,
+        Err(res) => return res.into_response(),
+    };
+    let tpl = ReferralsTemplate {
+        version: GIT_HASH[0..8].to_string(),
+        domain: query.get("domain").cloned().unwrap_or_default(),
+        graph,
+    };
+    match tpl.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
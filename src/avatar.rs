@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{imageops::FilterType, ImageFormat, Rgba, RgbaImage};
+use tracing::{error, info};
+
+use crate::fnv1a;
+use crate::membership_model::Membership;
+
+pub const AVATAR_DIR: &str = "resources/avatar";
+// 卡片上头像显示为 24px，按两倍像素保存，@2x 的 PNG 也足够清晰
+const AVATAR_SIZE: u32 = 48;
+// 按顺序查找的文件，内容按文件头识别，扩展名不必与格式一致
+const AVATAR_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+// 生成头像：5×5 的对称格子，四周各留半格
+const IDENTICON_CELLS: u32 = 5;
+const IDENTICON_BACKGROUND: Rgba<u8> = Rgba([240, 240, 240, 255]);
+
+// 源文件的路径、修改时间与大小，任一变化时重新处理
+type SourceStamp = (PathBuf, Option<SystemTime>, u64);
+
+struct Avatar {
+    source: Option<SourceStamp>,
+    // 缩放后的 PNG，base64
+    png: Arc<String>,
+}
+
+// 处理好的成员头像，启动时加载，定时任务中按文件变化重新加载
+pub struct AvatarStore {
+    dir: PathBuf,
+    entries: RwLock<HashMap<i64, Avatar>>,
+}
+
+impl AvatarStore {
+    pub fn load(dir: impl AsRef<Path>, members: &HashMap<i64, Membership>) -> Self {
+        let store = AvatarStore {
+            dir: dir.as_ref().to_path_buf(),
+            entries: RwLock::new(HashMap::new()),
+        };
+        store.refresh(members);
+        store
+    }
+
+    pub fn get(&self, member: &Membership) -> Arc<String> {
+        match self.entries.read().unwrap().get(&member.id) {
+            Some(avatar) => avatar.png.clone(),
+            None => Arc::new(identicon(&member.domain)),
+        }
+    }
+
+    // 重新处理新增、修改或删除了头像文件的成员，返回是否有变化
+    pub fn refresh(&self, members: &HashMap<i64, Membership>) -> bool {
+        let mut changed = 0;
+        for member in members.values() {
+            let source = self.find_source(member.id);
+            let current = self
+                .entries
+                .read()
+                .unwrap()
+                .get(&member.id)
+                .map(|a| a.source.clone());
+            if current.as_ref() == Some(&source) {
+                continue;
+            }
+            let png = match &source {
+                Some((path, _, _)) => process(path).unwrap_or_else(|e| {
+                    error!("avatar {}: {}", path.display(), e);
+                    identicon(&member.domain)
+                }),
+                None => identicon(&member.domain),
+            };
+            self.entries.write().unwrap().insert(
+                member.id,
+                Avatar {
+                    source,
+                    png: Arc::new(png),
+                },
+            );
+            changed += 1;
+        }
+        // 已不在注册表中的成员
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|id, _| members.contains_key(id));
+        if changed > 0 {
+            info!("loaded {} avatars", changed);
+        }
+        changed > 0
+    }
+
+    fn find_source(&self, id: i64) -> Option<SourceStamp> {
+        AVATAR_EXTENSIONS.iter().find_map(|ext| {
+            let path = self.dir.join(format!("{}.{}", id, ext));
+            let meta = std::fs::metadata(&path).ok().filter(|m| m.is_file())?;
+            Some((path, meta.modified().ok(), meta.len()))
+        })
+    }
+}
+
+// 居中裁成正方形，缩放到 AVATAR_SIZE 后编码为 PNG
+fn process(path: &Path) -> Result<String, anyhow::Error> {
+    let img = image::load_from_memory(&std::fs::read(path)?)?;
+    let side = img.width().min(img.height());
+    if side == 0 {
+        return Err(anyhow!("empty image"));
+    }
+    let img = img
+        .crop_imm(
+            (img.width() - side) / 2,
+            (img.height() - side) / 2,
+            side,
+            side,
+        )
+        .resize_exact(AVATAR_SIZE, AVATAR_SIZE, FilterType::Lanczos3);
+    encode(&img.to_rgba8())
+}
+
+fn encode(img: &RgbaImage) -> Result<String, anyhow::Error> {
+    let mut buf = Cursor::new(Vec::new());
+    img.write_to(&mut buf, ImageFormat::Png)?;
+    Ok(STANDARD.encode(buf.into_inner()))
+}
+
+// 色相由域名决定，饱和度与亮度固定
+fn hue_to_rgb(hue: f32) -> Rgba<u8> {
+    let (s, l) = (0.55_f32, 0.55_f32);
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = hue / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c / 2.0;
+    let channel = |v: f32| ((v + m) * 255.0).round() as u8;
+    Rgba([channel(r), channel(g), channel(b), 255])
+}
+
+// 没有头像的成员按域名生成左右对称的格子图案
+pub fn identicon(domain: &str) -> String {
+    // 图案只取决于域名
+    let hash = fnv1a(domain.to_lowercase().as_bytes());
+    let color = hue_to_rgb((hash >> 40) as f32 % 360.0);
+    let cell = AVATAR_SIZE / (IDENTICON_CELLS + 1);
+    let margin = (AVATAR_SIZE - cell * IDENTICON_CELLS) / 2;
+    let half = IDENTICON_CELLS.div_ceil(2);
+    let img = RgbaImage::from_fn(AVATAR_SIZE, AVATAR_SIZE, |x, y| {
+        if x < margin || y < margin {
+            return IDENTICON_BACKGROUND;
+        }
+        let (col, row) = ((x - margin) / cell, (y - margin) / cell);
+        if col >= IDENTICON_CELLS || row >= IDENTICON_CELLS {
+            return IDENTICON_BACKGROUND;
+        }
+        let col = col.min(IDENTICON_CELLS - 1 - col);
+        if (hash >> (row * half + col)) & 1 == 1 {
+            color
+        } else {
+            IDENTICON_BACKGROUND
+        }
+    });
+    // 编码内存中的图片不会失败
+    encode(&img).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    fn decode(png: &str) -> DynamicImage {
+        let bytes = STANDARD.decode(png).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Png);
+        image::load_from_memory(&bytes).unwrap()
+    }
+
+    #[test]
+    fn identicon_depends_only_on_domain() {
+        let a = identicon("example.com");
+        assert_eq!(a, identicon("example.com"));
+        assert_eq!(a, identicon("Example.COM"));
+        assert_ne!(a, identicon("example.org"));
+
+        let img = decode(&a);
+        assert_eq!((img.width(), img.height()), (AVATAR_SIZE, AVATAR_SIZE));
+        // 四周留白，左右对称
+        let img = img.to_rgba8();
+        assert_eq!(*img.get_pixel(0, 0), IDENTICON_BACKGROUND);
+        for y in 0..AVATAR_SIZE {
+            for x in 0..AVATAR_SIZE / 2 {
+                assert_eq!(img.get_pixel(x, y), img.get_pixel(AVATAR_SIZE - 1 - x, y));
+            }
+        }
+    }
+
+    // 横向的 JPEG 以 .png 为扩展名保存：按内容识别格式，居中裁掉两侧后缩放
+    #[test]
+    fn process_crops_and_resizes_by_content() {
+        let red = Rgb([220, 30, 30]);
+        let green = Rgb([30, 200, 30]);
+        let src = RgbImage::from_fn(
+            240,
+            120,
+            |x, _| {
+                if (60..180).contains(&x) {
+                    red
+                } else {
+                    green
+                }
+            },
+        );
+        let path =
+            std::env::temp_dir().join(format!("domaincards-avatar-{}.png", std::process::id()));
+        DynamicImage::ImageRgb8(src)
+            .save_with_format(&path, ImageFormat::Jpeg)
+            .unwrap();
+        let png = process(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let img = decode(&png.unwrap()).to_rgb8();
+        assert_eq!((img.width(), img.height()), (AVATAR_SIZE, AVATAR_SIZE));
+        for (x, y) in [
+            (0, 0),
+            (AVATAR_SIZE - 1, 0),
+            (AVATAR_SIZE / 2, AVATAR_SIZE / 2),
+        ] {
+            let Rgb([r, g, _]) = *img.get_pixel(x, y);
+            assert!(r > 150 && g < 100, "pixel ({}, {}) is not red", x, y);
+        }
+    }
+}
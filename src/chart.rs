@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::body::Bytes;
+use chrono::{Datelike, Duration, NaiveDate};
+
+use crate::{
+    i18n::{EmbedText, Lang},
+    sparkline::{daily_points, DailyPoints},
+    svg_template::Theme,
+    text_metrics::text_width,
+    DbPool,
+};
+
+// 画布与绘图区：左侧为纵轴刻度，上方为标题与图例，下方为日期
+const CHART_WIDTH: f32 = 600.0;
+const CHART_HEIGHT: f32 = 240.0;
+const PLOT_LEFT: f32 = 44.0;
+const PLOT_RIGHT: f32 = 576.0;
+const PLOT_TOP: f32 = 48.0;
+const PLOT_BOTTOM: f32 = 212.0;
+// 纵轴大约的刻度数
+const Y_TICKS: i64 = 4;
+// 图例文字的字号，与模板一致
+const LEGEND_FONT_SIZE: f32 = 11.0;
+// 渲染结果的条目上限，超出时清空重建
+const CHART_CACHE_CAPACITY: usize = 1024;
+
+// ?range=30d|90d|1y
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChartRange {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl ChartRange {
+    pub fn parse(query: &HashMap<String, String>) -> Result<Self, String> {
+        match query.get("range").map(|v| v.trim()).unwrap_or("") {
+            "" | "30" | "30d" => Ok(ChartRange::Month),
+            "90" | "90d" => Ok(ChartRange::Quarter),
+            "365" | "365d" | "1y" => Ok(ChartRange::Year),
+            other => Err(format!("invalid range {}, expected 30d, 90d or 1y", other)),
+        }
+    }
+
+    pub fn days(&self) -> i64 {
+        match self {
+            ChartRange::Month => 30,
+            ChartRange::Quarter => 90,
+            ChartRange::Year => 365,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChartSeries {
+    UniqueVisitor,
+    Referrer,
+}
+
+impl ChartSeries {
+    // ?series=uv,referrer，按给出的顺序绘制，缺省为两者
+    pub fn parse(query: &HashMap<String, String>) -> Result<Vec<Self>, String> {
+        let value = query.get("series").map(|v| v.trim()).unwrap_or("");
+        if value.is_empty() {
+            return Ok(vec![ChartSeries::UniqueVisitor, ChartSeries::Referrer]);
+        }
+        let mut series = Vec::new();
+        for name in value.split(',').map(|s| s.trim()) {
+            let s = match name {
+                "uv" => ChartSeries::UniqueVisitor,
+                "referrer" => ChartSeries::Referrer,
+                other => {
+                    return Err(format!(
+                        "invalid series {}, expected uv and/or referrer",
+                        other
+                    ))
+                }
+            };
+            if !series.contains(&s) {
+                series.push(s);
+            }
+        }
+        Ok(series)
+    }
+
+    // 模板中的样式名
+    pub fn class(&self) -> &'static str {
+        match self {
+            ChartSeries::UniqueVisitor => "uv",
+            ChartSeries::Referrer => "referrer",
+        }
+    }
+
+    fn value(&self, point: &(i64, i64)) -> i64 {
+        match self {
+            ChartSeries::UniqueVisitor => point.0,
+            ChartSeries::Referrer => point.1,
+        }
+    }
+
+    fn label(&self, text: &EmbedText) -> &'static str {
+        match self {
+            ChartSeries::UniqueVisitor => text.unique_visitor,
+            ChartSeries::Referrer => text.referrer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChartKey {
+    member: i64,
+    range: ChartRange,
+    series: Vec<ChartSeries>,
+    theme: String,
+    lang: Lang,
+}
+
+impl ChartKey {
+    pub fn new(
+        member: i64,
+        range: ChartRange,
+        series: &[ChartSeries],
+        theme: &Theme,
+        lang: Lang,
+    ) -> Self {
+        ChartKey {
+            member,
+            range,
+            series: series.to_vec(),
+            theme: format!("{}{}", theme.mode.as_str(), theme.accent),
+            lang,
+        }
+    }
+}
+
+// 日期与今天的 (独立访客, referrer)，不变时图也不变
+pub type ChartState = (NaiveDate, i64, i64);
+
+type HistoryEntries = HashMap<(i64, ChartRange), (NaiveDate, Arc<DailyPoints>)>;
+
+// 今天以前的每日计数按天缓存；渲染好的 SVG 在日期与今天的计数不变时直接返回
+#[derive(Default)]
+pub struct ChartCache {
+    history: Mutex<HistoryEntries>,
+    rendered: Mutex<HashMap<ChartKey, (ChartState, Bytes)>>,
+}
+
+impl ChartCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(
+        &self,
+        db_pool: &DbPool,
+        member: i64,
+        range: ChartRange,
+        today: NaiveDate,
+    ) -> Result<Arc<DailyPoints>, anyhow::Error> {
+        if let Some((day, points)) = self.history.lock().unwrap().get(&(member, range)) {
+            if *day == today {
+                return Ok(points.clone());
+            }
+        }
+        let points = Arc::new(daily_points(db_pool, member, range.days(), today)?);
+        self.history
+            .lock()
+            .unwrap()
+            .insert((member, range), (today, points.clone()));
+        Ok(points)
+    }
+
+    pub fn get_or_render<F>(
+        &self,
+        key: ChartKey,
+        state: ChartState,
+        svg: F,
+    ) -> Result<Bytes, askama::Error>
+    where
+        F: FnOnce() -> Result<String, askama::Error>,
+    {
+        if let Some((cached, svg)) = self.rendered.lock().unwrap().get(&key) {
+            if *cached == state {
+                return Ok(svg.clone());
+            }
+        }
+        let svg = Bytes::from(svg()?);
+        let mut rendered = self.rendered.lock().unwrap();
+        if rendered.len() >= CHART_CACHE_CAPACITY {
+            rendered.clear();
+        }
+        rendered.insert(key, (state, svg.clone()));
+        Ok(svg)
+    }
+}
+
+pub struct ChartLine {
+    pub class: &'static str,
+    pub path: String,
+}
+
+pub struct ChartLabel {
+    pub x: f32,
+    pub y: f32,
+    pub text: String,
+}
+
+pub struct Chart {
+    pub width: f32,
+    pub height: f32,
+    pub plot_left: f32,
+    pub plot_right: f32,
+    pub plot_top: f32,
+    pub plot_bottom: f32,
+    // 一年的点较密，改用细实线
+    pub dense: bool,
+    // 起止日期，上海时间
+    pub period: String,
+    pub y_ticks: Vec<ChartLabel>,
+    pub x_ticks: Vec<ChartLabel>,
+    // 第一条折线下方的填充
+    pub area: Option<String>,
+    pub lines: Vec<ChartLine>,
+    // 图例：色块位置、样式名与文字
+    pub legend: Vec<(f32, &'static str, ChartLabel)>,
+}
+
+// 1、2、5 乘以 10 的幂中不小于 max / ticks 的最小值
+fn nice_step(max: i64, ticks: i64) -> i64 {
+    let raw = (max as f64 / ticks as f64).max(1.0);
+    let magnitude = 10f64.powi(raw.log10().floor() as i32);
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= raw)
+        .unwrap_or(10.0 * magnitude) as i64
+}
+
+impl Chart {
+    // points 为截至 today 的每日计数，最后一项是今天
+    pub fn build(
+        points: &[(i64, i64)],
+        series: &[ChartSeries],
+        range: ChartRange,
+        today: NaiveDate,
+        text: &EmbedText,
+    ) -> Self {
+        let n = points.len().max(2);
+        let start = today - Duration::days(points.len().max(1) as i64 - 1);
+        let max = points
+            .iter()
+            .flat_map(|p| series.iter().map(|s| s.value(p)))
+            .max()
+            .unwrap_or(0);
+        let step = nice_step(max, Y_TICKS);
+        let y_max = (((max + step - 1) / step) * step).max(step);
+
+        let x_of = |i: usize| PLOT_LEFT + (PLOT_RIGHT - PLOT_LEFT) * i as f32 / (n - 1) as f32;
+        let y_of = |v: i64| PLOT_BOTTOM - (PLOT_BOTTOM - PLOT_TOP) * v as f32 / y_max as f32;
+
+        let y_ticks = (0..=y_max / step)
+            .map(|i| ChartLabel {
+                x: PLOT_LEFT - 6.0,
+                y: y_of(i * step),
+                text: (i * step).to_string(),
+            })
+            .collect();
+
+        // 一年按月标注，每年 1 月写年份；其余从今天往前每隔固定天数标注
+        let x_ticks = points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, _)| {
+                let day = start + Duration::days(i as i64);
+                let label = match range {
+                    ChartRange::Year if day.day() == 1 && day.month() == 1 => {
+                        day.year().to_string()
+                    }
+                    ChartRange::Year if day.day() == 1 => {
+                        text.months[day.month0() as usize].to_string()
+                    }
+                    ChartRange::Year => return None,
+                    _ => {
+                        let interval = if range == ChartRange::Month { 5 } else { 15 };
+                        if !(points.len() - 1 - i).is_multiple_of(interval) {
+                            return None;
+                        }
+                        day.format("%m-%d").to_string()
+                    }
+                };
+                Some(ChartLabel {
+                    x: x_of(i),
+                    y: PLOT_BOTTOM + 16.0,
+                    text: label,
+                })
+            })
+            .collect();
+
+        let line = |s: &ChartSeries| {
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    format!(
+                        "{}{:.1} {:.1}",
+                        if i == 0 { "M" } else { "L" },
+                        x_of(i),
+                        y_of(s.value(p))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let lines: Vec<ChartLine> = series
+            .iter()
+            .map(|s| ChartLine {
+                class: s.class(),
+                path: line(s),
+            })
+            .collect();
+        let area = lines.first().filter(|_| !points.is_empty()).map(|l| {
+            format!(
+                "{} L{:.1} {:.1} L{:.1} {:.1} Z",
+                l.path,
+                x_of(points.len() - 1),
+                PLOT_BOTTOM,
+                PLOT_LEFT,
+                PLOT_BOTTOM
+            )
+        });
+
+        // 图例靠右排列，色块 12px，与文字间隔 4px，各项间隔 12px
+        let mut right = PLOT_RIGHT;
+        let mut legend: Vec<(f32, &'static str, ChartLabel)> = series
+            .iter()
+            .rev()
+            .map(|s| {
+                let label = s.label(text);
+                let text_x = right - text_width(label, LEGEND_FONT_SIZE);
+                let swatch_x = text_x - 16.0;
+                right = swatch_x - 12.0;
+                (
+                    swatch_x,
+                    s.class(),
+                    ChartLabel {
+                        x: text_x,
+                        y: 20.0,
+                        text: label.to_string(),
+                    },
+                )
+            })
+            .collect();
+        legend.reverse();
+
+        Chart {
+            width: CHART_WIDTH,
+            height: CHART_HEIGHT,
+            plot_left: PLOT_LEFT,
+            plot_right: PLOT_RIGHT,
+            plot_top: PLOT_TOP,
+            plot_bottom: PLOT_BOTTOM,
+            dense: range == ChartRange::Year,
+            period: format!(
+                "{} – {} (UTC+8)",
+                start.format("%Y-%m-%d"),
+                today.format("%Y-%m-%d")
+            ),
+            y_ticks,
+            x_ticks,
+            area,
+            lines,
+            legend,
+        }
+    }
+}
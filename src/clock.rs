@@ -0,0 +1,108 @@
+use std::sync::Mutex;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+use crate::now_shanghai;
+
+// 统计所用时区的本地时间，测试中可替换为手动时钟
+pub trait Clock: Send + Sync {
+    fn now(&self) -> NaiveDateTime;
+
+    fn today(&self) -> NaiveDate {
+        self.now().date()
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        now_shanghai()
+    }
+}
+
+pub struct ManualClock {
+    now: Mutex<NaiveDateTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: NaiveDateTime) -> Self {
+        ManualClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: NaiveDateTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+pub fn start_of_day(day: NaiveDate) -> NaiveDateTime {
+    NaiveDateTime::new(day, NaiveTime::from_hms(0, 0, 0))
+}
+
+// 下一次唤醒时间：整 interval 分钟的刻度与下一个 00:00 中较早者
+pub fn next_wakeup(now: NaiveDateTime, interval_minutes: u32) -> NaiveDateTime {
+    let midnight = start_of_day(now.date()) + Duration::days(1);
+    let minutes = now.hour() * 60 + now.minute();
+    let next_tick = start_of_day(now.date())
+        + Duration::minutes(((minutes / interval_minutes + 1) * interval_minutes) as i64);
+    next_tick.min(midnight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .and_then(|d| d.and_hms_opt(hour, minute, second))
+            .unwrap()
+    }
+
+    #[test]
+    fn wakes_on_next_interval_tick() {
+        assert_eq!(next_wakeup(at(18, 12, 3, 0), 5), at(18, 12, 5, 0));
+        assert_eq!(next_wakeup(at(18, 12, 5, 0), 5), at(18, 12, 10, 0));
+        assert_eq!(next_wakeup(at(18, 0, 0, 0), 5), at(18, 0, 5, 0));
+    }
+
+    // 上海时间 23:59 之后的下一次唤醒正好是次日 00:00，不会越过跨天
+    #[test]
+    fn wakes_at_midnight_before_day_ends() {
+        assert_eq!(next_wakeup(at(18, 23, 58, 0), 5), at(19, 0, 0, 0));
+        assert_eq!(next_wakeup(at(18, 23, 59, 59), 5), at(19, 0, 0, 0));
+        assert_eq!(next_wakeup(at(18, 23, 57, 0), 7), at(19, 0, 0, 0));
+    }
+
+    #[test]
+    fn manual_clock_moves_across_midnight() {
+        let clock = ManualClock::new(at(18, 23, 59, 0));
+        assert_eq!(
+            clock.today(),
+            NaiveDate::from_ymd_opt(2026, 10, 18).unwrap()
+        );
+        clock.advance(Duration::minutes(1));
+        assert_eq!(clock.now(), at(19, 0, 0, 0));
+        assert_eq!(
+            clock.today(),
+            NaiveDate::from_ymd_opt(2026, 10, 19).unwrap()
+        );
+        clock.set(at(18, 8, 0, 0));
+        assert_eq!(
+            clock.today(),
+            NaiveDate::from_ymd_opt(2026, 10, 18).unwrap()
+        );
+    }
+}
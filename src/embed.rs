@@ -0,0 +1,214 @@
+use std::{collections::HashMap, env};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::SYSTEM_DOMAIN;
+
+// 令牌取 HMAC-SHA256 的前 12 字节，base64url 后为 16 个字符
+const TOKEN_BYTES: usize = 12;
+
+// 嵌入令牌的密钥，未设置时不签发也不接受令牌
+lazy_static! {
+    static ref EMBED_TOKEN_SECRET: Option<String> = env::var("EMBED_TOKEN_SECRET")
+        .ok()
+        .filter(|s| !s.is_empty());
+}
+
+fn mac(secret: &str, domain: &str) -> Hmac<Sha256> {
+    // HMAC 接受任意长度的密钥
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(domain.to_lowercase().as_bytes());
+    mac
+}
+
+// 成员的嵌入令牌，只对该成员的域名有效
+pub fn sign(domain: &str) -> Option<String> {
+    EMBED_TOKEN_SECRET
+        .as_ref()
+        .map(|secret| sign_with(secret, domain))
+}
+
+fn sign_with(secret: &str, domain: &str) -> String {
+    let tag = mac(secret, domain).finalize().into_bytes();
+    URL_SAFE_NO_PAD.encode(&tag[..TOKEN_BYTES])
+}
+
+// ?token= 与域名匹配时返回 true；密钥更换后旧令牌失效，按没有令牌处理
+pub fn verified(query: &HashMap<String, String>, domain: &str) -> bool {
+    match (EMBED_TOKEN_SECRET.as_ref(), query.get("token")) {
+        (Some(secret), Some(token)) => verify_with(secret, token, domain),
+        _ => false,
+    }
+}
+
+fn verify_with(secret: &str, token: &str, domain: &str) -> bool {
+    match URL_SAFE_NO_PAD.decode(token.trim()) {
+        Ok(tag) if tag.len() == TOKEN_BYTES => {
+            mac(secret, domain).verify_truncated_left(&tag).is_ok()
+        }
+        _ => false,
+    }
+}
+
+// 一种嵌入方式在各格式下的代码
+#[derive(Serialize)]
+pub struct Snippet {
+    pub kind: &'static str,
+    pub image: String,
+    pub markdown: String,
+    pub html: String,
+    pub bbcode: String,
+}
+
+#[derive(Serialize)]
+pub struct EmbedSnippets {
+    pub domain: String,
+    // 不是成员时为 false，代码只是预览
+    pub member: bool,
+    pub token: Option<String>,
+    pub snippets: Vec<Snippet>,
+}
+
+// 预览只接受形如域名的输入，避免把任意文本写进代码
+pub fn plausible_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.len() <= 253
+        && domain.contains('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+impl EmbedSnippets {
+    // 成员的嵌入代码，Markdown 与 BBCode 带令牌
+    pub fn new(domain: &str) -> Self {
+        EmbedSnippets {
+            member: true,
+            ..Self::build(domain, sign(domain))
+        }
+    }
+
+    // 尚未加入的域名只给出不带令牌的代码，加入后才会计数
+    pub fn preview(domain: &str) -> Self {
+        Self::build(domain, None)
+    }
+
+    // 加入页面输入域名前展示的示例代码
+    pub fn placeholder() -> Self {
+        Self::preview("[domain]")
+    }
+
+    // HTML 放在成员自己的站点上，按 Referer 计数；Markdown 与 BBCode 多用于 README、论坛，带上令牌
+    fn build(domain: &str, token: Option<String>) -> Self {
+        let home = format!("https://{}/", *SYSTEM_DOMAIN);
+        let signed = |url: String| match &token {
+            Some(token) => format!("{}?token={}", url, token),
+            None => url,
+        };
+        let title = "米表联盟[Domain.Cards]";
+        let mut snippets: Vec<Snippet> = ["icon", "badge", "card"]
+            .iter()
+            .map(|kind| {
+                let image = format!("{}api/{}/{}", home, kind, domain);
+                Snippet {
+                    kind,
+                    markdown: format!("[![{}]({})]({})", title, signed(image.clone()), home),
+                    html: format!(
+                        "<a title=\"{}\" href=\"{}\"><img height=\"18px\" src=\"{}\"></a>",
+                        title, home, image
+                    ),
+                    // 论坛大多不显示 SVG，使用 PNG
+                    bbcode: format!(
+                        "[url={}][img]{}[/img][/url]",
+                        home,
+                        signed(format!("{}api/{}/{}.png", home, kind, domain))
+                    ),
+                    image,
+                }
+            })
+            .collect();
+        // shields.io 的服务器代为请求，不计入访问
+        let endpoint = format!("{}api/shields/{}", home, domain);
+        let image = format!(
+            "https://img.shields.io/endpoint?url={}",
+            url::form_urlencoded::byte_serialize(endpoint.as_bytes()).collect::<String>()
+        );
+        snippets.push(Snippet {
+            kind: "shields",
+            markdown: format!("[![{}]({})]({})", title, image, home),
+            html: format!(
+                "<a title=\"{}\" href=\"{}\"><img src=\"{}\"></a>",
+                title, home, image
+            ),
+            bbcode: format!("[url={}][img]{}[/img][/url]", home, image),
+            image,
+        });
+        // 访问日历同样只展示统计，不带令牌
+        let image = format!("{}api/heatmap/{}", home, domain);
+        snippets.push(Snippet {
+            kind: "heatmap",
+            markdown: format!("[![{}]({})]({})", title, image, home),
+            html: format!(
+                "<a title=\"{}\" href=\"{}\"><img src=\"{}\"></a>",
+                title, home, image
+            ),
+            bbcode: format!("[url={}][img]{}[/img][/url]", home, image),
+            image,
+        });
+        EmbedSnippets {
+            domain: domain.to_string(),
+            member: false,
+            token,
+            snippets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn signed_token_verifies_for_its_domain() {
+        let token = sign_with(SECRET, "example.com");
+        assert_eq!(token.len(), 16);
+        assert!(verify_with(SECRET, &token, "example.com"));
+        assert!(verify_with(SECRET, &format!(" {} ", token), "example.com"));
+        // 同一密钥、域名总是得到同一令牌
+        assert_eq!(token, sign_with(SECRET, "example.com"));
+    }
+
+    #[test]
+    fn domain_is_case_insensitive() {
+        let token = sign_with(SECRET, "Example.COM");
+        assert_eq!(token, sign_with(SECRET, "example.com"));
+        assert!(verify_with(SECRET, &token, "EXAMPLE.com"));
+    }
+
+    #[test]
+    fn token_is_rejected_for_other_domain_or_secret() {
+        let token = sign_with(SECRET, "a.example");
+        assert!(!verify_with(SECRET, &token, "b.example"));
+        assert!(!verify_with("other-secret", &token, "a.example"));
+    }
+
+    #[test]
+    fn malformed_token_is_rejected() {
+        let token = sign_with(SECRET, "example.com");
+        assert!(!verify_with(SECRET, "", "example.com"));
+        assert!(!verify_with(SECRET, &token[..12], "example.com"));
+        assert!(!verify_with(
+            SECRET,
+            &format!("{}AAAA", token),
+            "example.com"
+        ));
+        assert!(!verify_with(SECRET, "!!!!!!!!!!!!!!!!", "example.com"));
+        assert!(!verify_with(SECRET, &format!("{}==", token), "example.com"));
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{Datelike, Duration, NaiveDate};
+
+use crate::{clock::start_of_day, i18n::EmbedText, statistics_model::Statistics, DbPool};
+
+// 格子边长与间距，左侧留出星期、上方留出标题与月份
+const CELL_SIZE: f32 = 10.0;
+const CELL_STEP: f32 = 12.0;
+const GRID_LEFT: f32 = 32.0;
+const GRID_TOP: f32 = 36.0;
+const MARGIN: f32 = 8.0;
+// 图例所在行的高度
+const LEGEND_HEIGHT: f32 = 24.0;
+// 按当天访问量与区间最大值之比分为 0–4 五档
+pub const HEATMAP_LEVELS: i64 = 4;
+// 可选年份的下限
+const MIN_YEAR: i32 = 2000;
+
+// ?year=，缺省为截至今天的一年
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeatmapRange {
+    LastYear,
+    Year(i32),
+}
+
+impl HeatmapRange {
+    pub fn parse(query: &HashMap<String, String>, today: NaiveDate) -> Result<Self, String> {
+        match query.get("year").map(|v| v.trim()).unwrap_or("") {
+            "" | "last" => Ok(HeatmapRange::LastYear),
+            year => match year.parse::<i32>() {
+                Ok(y) if (MIN_YEAR..=today.year()).contains(&y) => Ok(HeatmapRange::Year(y)),
+                _ => Err(format!(
+                    "invalid year {}, expected last or {} to {}",
+                    year,
+                    MIN_YEAR,
+                    today.year()
+                )),
+            },
+        }
+    }
+
+    // 闭区间，今年只到今天
+    pub fn days(&self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        let last_year = (today - Duration::days(364), today);
+        match self {
+            HeatmapRange::LastYear => last_year,
+            // parse 已限定年份，超出日期范围时按最近一年处理
+            HeatmapRange::Year(y) => NaiveDate::from_ymd_opt(*y, 1, 1)
+                .zip(NaiveDate::from_ymd_opt(*y, 12, 31))
+                .map(|(first, last)| (first, last.min(today)))
+                .unwrap_or(last_year),
+        }
+    }
+}
+
+// 每日独立访客与 referrer 之和
+pub type DailyVisits = HashMap<NaiveDate, i64>;
+
+type HeatmapEntries = HashMap<(i64, HeatmapRange), (NaiveDate, Arc<DailyVisits>)>;
+
+// 各成员今天以前的每日访问，当天内不变，跨天后重新读取
+#[derive(Default)]
+pub struct HeatmapCache {
+    entries: Mutex<HeatmapEntries>,
+}
+
+impl HeatmapCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(
+        &self,
+        db_pool: &DbPool,
+        member: i64,
+        range: HeatmapRange,
+        today: NaiveDate,
+    ) -> Result<Arc<DailyVisits>, anyhow::Error> {
+        if let Some((day, visits)) = self.entries.lock().unwrap().get(&(member, range)) {
+            if *day == today {
+                return Ok(visits.clone());
+            }
+        }
+        let (start, end) = range.days(today);
+        let end = end.min(today - Duration::days(1));
+        let visits: DailyVisits = if end < start {
+            HashMap::new()
+        } else {
+            Statistics::between(
+                db_pool.get()?,
+                Some(member),
+                start_of_day(start),
+                start_of_day(end + Duration::days(1)) - Duration::seconds(1),
+            )?
+            .iter()
+            .map(|s| (s.created_at.date(), s.unique_visitor + s.referrer))
+            .collect()
+        };
+        let visits = Arc::new(visits);
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((member, range), (today, visits.clone()));
+        Ok(visits)
+    }
+}
+
+pub struct HeatmapCell {
+    pub x: f32,
+    pub y: f32,
+    pub date: String,
+    pub visits: i64,
+    pub level: i64,
+}
+
+pub struct HeatmapLabel {
+    pub x: f32,
+    pub y: f32,
+    pub text: &'static str,
+}
+
+// 按周排列的日历，每列一周、周一在上
+pub struct Heatmap {
+    pub width: f32,
+    pub height: f32,
+    pub cell_size: f32,
+    pub cells: Vec<HeatmapCell>,
+    pub months: Vec<HeatmapLabel>,
+    pub weekdays: Vec<HeatmapLabel>,
+    pub total: i64,
+    // 图例中五档格子的横坐标与纵坐标
+    pub legend: Vec<(f32, i64)>,
+    pub legend_y: f32,
+}
+
+impl Heatmap {
+    pub fn build(
+        (start, end): (NaiveDate, NaiveDate),
+        visits: &DailyVisits,
+        text: &EmbedText,
+    ) -> Self {
+        let first_monday = start - Duration::days(start.weekday().num_days_from_monday() as i64);
+        let weeks = (end - first_monday).num_days() / 7 + 1;
+        let max = visits
+            .iter()
+            .filter(|(d, _)| (start..=end).contains(*d))
+            .map(|(_, v)| *v)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let position = |day: NaiveDate| {
+            let offset = (day - first_monday).num_days();
+            (
+                GRID_LEFT + CELL_STEP * (offset / 7) as f32,
+                GRID_TOP + CELL_STEP * (offset % 7) as f32,
+            )
+        };
+
+        let mut cells = Vec::new();
+        let mut months = Vec::new();
+        let mut total = 0;
+        let mut day = start;
+        while day <= end {
+            let value = visits.get(&day).cloned().unwrap_or(0);
+            let (x, y) = position(day);
+            // 月份标在该月 1 日所在的列，最后一列放不下时省略
+            if day.day() == 1 && x + CELL_STEP * 2.0 <= GRID_LEFT + CELL_STEP * weeks as f32 {
+                months.push(HeatmapLabel {
+                    x,
+                    y: GRID_TOP - 6.0,
+                    text: text.months[day.month0() as usize],
+                });
+            }
+            cells.push(HeatmapCell {
+                x,
+                y,
+                date: day.format("%Y-%m-%d").to_string(),
+                visits: value,
+                level: match value {
+                    v if v <= 0 => 0,
+                    v => ((v * HEATMAP_LEVELS + max - 1) / max).clamp(1, HEATMAP_LEVELS),
+                },
+            });
+            total += value;
+            day += Duration::days(1);
+        }
+
+        // 只标周一、周三、周五
+        let weekdays = [0, 2, 4]
+            .iter()
+            .map(|i| HeatmapLabel {
+                x: GRID_LEFT - 6.0,
+                y: GRID_TOP + CELL_STEP * *i as f32 + CELL_SIZE - 1.0,
+                text: text.weekdays[*i],
+            })
+            .collect();
+
+        let width = GRID_LEFT + CELL_STEP * weeks as f32 + MARGIN;
+        let legend_y = GRID_TOP + CELL_STEP * 7.0 + MARGIN;
+        // 图例靠右，右侧留出“多”的位置
+        let legend_right = width - MARGIN - 28.0;
+        let legend = (0..=HEATMAP_LEVELS)
+            .map(|level| {
+                (
+                    legend_right - CELL_STEP * (HEATMAP_LEVELS - level + 1) as f32,
+                    level,
+                )
+            })
+            .collect();
+        Heatmap {
+            width,
+            height: legend_y + LEGEND_HEIGHT - MARGIN,
+            cell_size: CELL_SIZE,
+            cells,
+            months,
+            weekdays,
+            total,
+            legend,
+            legend_y,
+        }
+    }
+}
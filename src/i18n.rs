@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use axum::http::{header::ACCEPT_LANGUAGE, HeaderMap};
+
+// 徽章、卡片、图标支持的语言，缺省为中文
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lang {
+    Zh,
+    En,
+}
+
+// 嵌入图片上的固定文字
+pub struct EmbedText {
+    pub brand: &'static str,
+    pub brand_font_size: u32,
+    pub brand_letter_spacing: u32,
+    pub unique_visitor: &'static str,
+    pub referrer: &'static str,
+    pub level: &'static str,
+    // 热力图：月份、周一至周日、图例两端，以及 {total}、{year} 待替换的标题
+    pub months: [&'static str; 12],
+    pub weekdays: [&'static str; 7],
+    pub less: &'static str,
+    pub more: &'static str,
+    pub heatmap_last_year: &'static str,
+    pub heatmap_year: &'static str,
+}
+
+const ZH: EmbedText = EmbedText {
+    brand: "米表联盟",
+    brand_font_size: 13,
+    brand_letter_spacing: 2,
+    unique_visitor: "独立访客",
+    referrer: "引荐",
+    level: "等级",
+    months: [
+        "1月", "2月", "3月", "4月", "5月", "6月", "7月", "8月", "9月", "10月", "11月", "12月",
+    ],
+    weekdays: ["一", "二", "三", "四", "五", "六", "日"],
+    less: "少",
+    more: "多",
+    heatmap_last_year: "最近一年共 {total} 次访问",
+    heatmap_year: "{year} 年共 {total} 次访问",
+};
+
+// 英文品牌名较长，缩小字号以免与右侧的数据重叠
+const EN: EmbedText = EmbedText {
+    brand: "Domain Cards",
+    brand_font_size: 9,
+    brand_letter_spacing: 0,
+    unique_visitor: "Visitors",
+    referrer: "Referrers",
+    level: "Level",
+    months: [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ],
+    weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+    less: "Less",
+    more: "More",
+    heatmap_last_year: "{total} visits in the last year",
+    heatmap_year: "{total} visits in {year}",
+};
+
+impl Lang {
+    // 只看主标签，zh-CN、zh-Hant、en-US 等都可以
+    pub fn parse(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?;
+        if primary.eq_ignore_ascii_case("zh") {
+            Some(Lang::Zh)
+        } else if primary.eq_ignore_ascii_case("en") {
+            Some(Lang::En)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lang::Zh => "zh",
+            Lang::En => "en",
+        }
+    }
+
+    pub fn text(&self) -> &'static EmbedText {
+        match self {
+            Lang::Zh => &ZH,
+            Lang::En => &EN,
+        }
+    }
+
+    // ?lang= 优先，有误时报错；否则按 Accept-Language 的权重取第一个支持的语言
+    pub fn negotiate(query: &HashMap<String, String>, headers: &HeaderMap) -> Result<Self, String> {
+        if let Some(lang) = query
+            .get("lang")
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+        {
+            return Lang::parse(lang)
+                .ok_or_else(|| format!("invalid lang {}, expected zh or en", lang));
+        }
+        let accept = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        let mut ranges: Vec<(f32, Lang)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let lang = Lang::parse(parts.next()?)?;
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (q > 0.0).then_some((q, lang))
+            })
+            .collect();
+        // 权重相同时保持原顺序
+        ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(ranges.first().map(|r| r.1).unwrap_or(Lang::Zh))
+    }
+}
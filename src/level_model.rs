@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDate};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::SqliteConnection;
+use serde::Serialize;
+
+use crate::clock::start_of_day;
+use crate::statistics_model::Statistics;
+
+// 计算等级所用的窗口天数，窗口截至前一天
+pub const LEVEL_WINDOW_DAYS: i64 = 14;
+pub const MAX_LEVEL: i64 = 10;
+
+pub const LEVEL_FORMULA: &str = "score = (uv + rv) 在窗口内的日均值；\
+    score = 0 时为 1 级，否则 level = max(1, ceil(10 × 窗口内 score ≤ 自己的活跃成员数 / 活跃成员总数))";
+
+// 达到某一等级所需的最低得分，取实际达到该等级的成员中的最小值
+#[derive(Serialize, Debug, Clone)]
+pub struct LevelThreshold {
+    pub level: i64,
+    pub min_score: Option<f64>,
+    pub members: usize,
+}
+
+// 某一天的等级表，只依赖窗口内已经结束的日期，当天内不变
+#[derive(Debug, Clone)]
+pub struct LevelTable {
+    pub day: NaiveDate,
+    pub scores: HashMap<i64, f64>,
+    pub levels: HashMap<i64, i64>,
+}
+
+impl LevelTable {
+    // 窗口的首尾两天（含）
+    pub fn window(day: NaiveDate) -> (NaiveDate, NaiveDate) {
+        (
+            day - Duration::days(LEVEL_WINDOW_DAYS),
+            day - Duration::days(1),
+        )
+    }
+
+    pub fn load(
+        conn: PooledConnection<ConnectionManager<SqliteConnection>>,
+        day: NaiveDate,
+    ) -> Result<Self, anyhow::Error> {
+        let (start, end) = Self::window(day);
+        let rows = Statistics::between(conn, None, start_of_day(start), start_of_day(end))?;
+        Ok(Self::build(day, rows.iter()))
+    }
+
+    // rows 中窗口以外的日期会被忽略
+    pub fn build<'a>(day: NaiveDate, rows: impl Iterator<Item = &'a Statistics>) -> Self {
+        let (start, end) = Self::window(day);
+        let mut sums: HashMap<i64, i64> = HashMap::new();
+        rows.filter(|s| s.created_at.date() >= start && s.created_at.date() <= end)
+            .for_each(|s| {
+                *sums.entry(s.membership_id).or_default() += s.unique_visitor + s.referrer
+            });
+        let scores: HashMap<i64, f64> = sums
+            .into_iter()
+            .filter(|(_, v)| *v > 0)
+            .map(|(k, v)| (k, v as f64 / LEVEL_WINDOW_DAYS as f64))
+            .collect();
+
+        let mut sorted: Vec<f64> = scores.values().cloned().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let total = sorted.len();
+        let levels = scores
+            .iter()
+            .map(|(id, score)| {
+                let at_or_below = sorted.partition_point(|s| s <= score);
+                let level = (MAX_LEVEL * at_or_below as i64 + total as i64 - 1) / total as i64;
+                (*id, level.clamp(1, MAX_LEVEL))
+            })
+            .collect();
+        LevelTable {
+            day,
+            scores,
+            levels,
+        }
+    }
+
+    pub fn level_of(&self, id: &i64) -> i64 {
+        self.levels.get(id).cloned().unwrap_or(1)
+    }
+
+    pub fn score_of(&self, id: &i64) -> f64 {
+        self.scores.get(id).cloned().unwrap_or(0.0)
+    }
+
+    pub fn thresholds(&self) -> Vec<LevelThreshold> {
+        let mut by_level: BTreeMap<i64, (Option<f64>, usize)> =
+            (1..=MAX_LEVEL).map(|l| (l, (None, 0))).collect();
+        self.levels.iter().for_each(|(id, level)| {
+            let score = self.score_of(id);
+            let entry = by_level.get_mut(level).unwrap();
+            entry.0 = Some(entry.0.map_or(score, |s: f64| s.min(score)));
+            entry.1 += 1;
+        });
+        by_level
+            .into_iter()
+            .map(|(level, (min_score, members))| LevelThreshold {
+                level,
+                min_score,
+                members,
+            })
+            .collect()
+    }
+}
+
+// 区间内每一天的等级表，只读一次数据库
+pub fn daily_level_tables(
+    conn: PooledConnection<ConnectionManager<SqliteConnection>>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<HashMap<NaiveDate, LevelTable>, anyhow::Error> {
+    let rows = Statistics::between(
+        conn,
+        None,
+        start_of_day(LevelTable::window(from).0),
+        start_of_day(LevelTable::window(to).1),
+    )?;
+    let mut by_day: BTreeMap<NaiveDate, Vec<Statistics>> = BTreeMap::new();
+    rows.into_iter()
+        .for_each(|s| by_day.entry(s.created_at.date()).or_default().push(s));
+
+    let mut res = HashMap::new();
+    let mut day = from;
+    while day <= to {
+        let (start, end) = LevelTable::window(day);
+        let table = LevelTable::build(day, by_day.range(start..=end).flat_map(|(_, v)| v.iter()));
+        res.insert(day, table);
+        day += Duration::days(1);
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, 15).unwrap()
+    }
+
+    // 窗口内前一天的一行统计
+    fn row(member: i64, uv: i64, rv: i64) -> Statistics {
+        row_on(member, day() - Duration::days(1), uv, rv)
+    }
+
+    fn row_on(member: i64, date: NaiveDate, uv: i64, rv: i64) -> Statistics {
+        Statistics {
+            id: 0,
+            created_at: start_of_day(date),
+            updated_at: start_of_day(date),
+            membership_id: member,
+            unique_visitor: uv,
+            referrer: rv,
+            latest_referrer_at: None,
+        }
+    }
+
+    #[test]
+    fn empty_window_defaults_to_level_one() {
+        let table = LevelTable::build(day(), [].iter());
+        assert!(table.levels.is_empty());
+        assert_eq!(table.level_of(&1), 1);
+        assert_eq!(table.score_of(&1), 0.0);
+        assert!(table
+            .thresholds()
+            .iter()
+            .all(|t| t.min_score.is_none() && t.members == 0));
+    }
+
+    #[test]
+    fn zero_scores_stay_at_level_one() {
+        let rows = [row(1, 0, 0), row(2, 0, 0)];
+        let table = LevelTable::build(day(), rows.iter());
+        assert!(table.scores.is_empty());
+        assert_eq!(table.level_of(&1), 1);
+        assert_eq!(table.level_of(&2), 1);
+    }
+
+    #[test]
+    fn single_member_reaches_max_level() {
+        let rows = [row(1, 7, 7)];
+        let table = LevelTable::build(day(), rows.iter());
+        assert_eq!(table.score_of(&1), 1.0);
+        assert_eq!(table.level_of(&1), MAX_LEVEL);
+    }
+
+    #[test]
+    fn ties_share_the_higher_level() {
+        let rows = [row(1, 14, 0), row(2, 28, 0), row(3, 14, 14), row(4, 0, 28)];
+        let table = LevelTable::build(day(), rows.iter());
+        // 1/4 → ceil(2.5) = 3，并列的三个成员都是 4/4
+        assert_eq!(table.level_of(&1), 3);
+        assert_eq!(table.level_of(&2), MAX_LEVEL);
+        assert_eq!(table.level_of(&3), MAX_LEVEL);
+        assert_eq!(table.level_of(&4), MAX_LEVEL);
+    }
+
+    #[test]
+    fn rows_outside_window_are_ignored() {
+        let (start, _) = LevelTable::window(day());
+        let rows = [
+            row_on(1, start, 14, 0),
+            row_on(1, start - Duration::days(1), 100, 0),
+            row_on(1, day(), 100, 0),
+            row_on(2, day(), 100, 0),
+        ];
+        let table = LevelTable::build(day(), rows.iter());
+        assert_eq!(table.score_of(&1), 1.0);
+        assert_eq!(table.score_of(&2), 0.0);
+        assert_eq!(table.level_of(&2), 1);
+    }
+
+    #[test]
+    fn levels_stay_within_bounds() {
+        for members in 1..=25 {
+            let rows: Vec<Statistics> = (0..members).map(|i| row(i, i, 0)).collect();
+            let table = LevelTable::build(day(), rows.iter());
+            assert!(table.levels.values().all(|l| (1..=MAX_LEVEL).contains(l)));
+            // 成员 0 没有访问，不参与排名
+            assert_eq!(table.levels.len() as i64, members - 1);
+            if members > 1 {
+                assert_eq!(table.level_of(&(members - 1)), MAX_LEVEL);
+            }
+            let thresholds = table.thresholds();
+            assert_eq!(thresholds.len() as i64, MAX_LEVEL);
+            assert_eq!(
+                thresholds.iter().map(|t| t.members).sum::<usize>(),
+                table.levels.len()
+            );
+        }
+    }
+}
@@ -3,16 +3,37 @@ use std::env;
 use chrono::{NaiveDateTime, Utc};
 use chrono_tz::Asia::Shanghai;
 use diesel::{
-    r2d2::{ConnectionManager, Pool},
+    connection::SimpleConnection,
+    r2d2::{ConnectionManager, CustomizeConnection, Pool},
     SqliteConnection,
 };
 use lazy_static::lazy_static;
 
+pub mod api_router;
 pub mod app_model;
 pub mod app_router;
+pub mod avatar;
+pub mod chart;
+pub mod clock;
+pub mod embed;
+pub mod heatmap;
+pub mod i18n;
+pub mod level_model;
+pub mod lifecycle_model;
 pub mod membership_model;
+pub mod png_render;
+pub mod quality_model;
+pub mod rank_cache;
+pub mod rank_history_model;
+pub mod rank_listing;
+pub mod rank_strategy;
+pub mod referral_model;
 pub mod schema;
+pub mod sparkline;
+pub mod statistics_io;
 pub mod statistics_model;
+pub mod svg_template;
+pub mod text_metrics;
 
 extern crate diesel;
 
@@ -23,12 +44,29 @@ lazy_static! {
     static ref SYSTEM_DOMAIN: String = env::var("SYSTEM_DOMAIN").unwrap();
 }
 
+// 管理接口令牌，未设置时管理接口不可用
+lazy_static! {
+    static ref ADMIN_TOKEN: Option<String> = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+}
+
 pub type DbPool = Pool<ConnectionManager<SqliteConnection>>;
 
+// 多个实例共用同一数据库文件时，写入需要等待而不是直接返回 busy
+#[derive(Debug)]
+struct SqliteCustomizer;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SqliteCustomizer {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute("PRAGMA journal_mode = WAL; PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
 pub fn establish_connection(database_url: &str) -> DbPool {
     let manager = ConnectionManager::<SqliteConnection>::new(database_url);
     Pool::builder()
         .max_size(5)
+        .connection_customizer(Box::new(SqliteCustomizer))
         .build(manager)
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
@@ -36,3 +74,61 @@ pub fn establish_connection(database_url: &str) -> DbPool {
 pub fn now_shanghai() -> NaiveDateTime {
     Utc::now().with_timezone(&Shanghai).naive_local()
 }
+
+// FNV-1a 64 位，结果写入数据库或决定生成的头像，不随 Rust 版本或实例变化
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// 测试用的临时数据库：独立的 SQLite 文件，已执行迁移，离开作用域时删除
+#[cfg(test)]
+pub(crate) mod test_db {
+    use std::{
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+    use crate::{establish_connection, DbPool};
+
+    const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations/");
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    pub struct TestDb {
+        pub path: PathBuf,
+        pub pool: DbPool,
+    }
+
+    impl TestDb {
+        pub fn new() -> Self {
+            std::env::set_var("SYSTEM_DOMAIN", "domain.cards");
+            let path = std::env::temp_dir().join(format!(
+                "domaincards-test-{}-{}.db",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::SeqCst)
+            ));
+            let pool = Self::open(&path);
+            pool.get()
+                .unwrap()
+                .run_pending_migrations(MIGRATIONS)
+                .unwrap();
+            TestDb { path, pool }
+        }
+
+        // 同一文件上的另一个连接池，模拟共用数据库的另一个实例
+        pub fn open(path: &Path) -> DbPool {
+            establish_connection(path.to_str().unwrap())
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::env;
+
+use crate::schema::lifecycle_transitions::{self, dsl::*};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+use serde::Serialize;
+use tracing::error;
+
+// 缺省阈值（未活跃天数）：30 天提醒，60 天暂停，90 天移除
+const WARN_DAYS: i64 = 30;
+const SUSPEND_DAYS: i64 = 60;
+const REMOVE_DAYS: i64 = 90;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleState {
+    Active,
+    // 宽限期，照常进入榜单，排行榜页另列在“待暂停”中
+    Warned,
+    // 不出现在任何榜单中，统计照常记录
+    Suspended,
+    // 等待维护者从 membership.json 中删除
+    Removed,
+}
+
+pub const LIFECYCLE_STATES: [LifecycleState; 4] = [
+    LifecycleState::Active,
+    LifecycleState::Warned,
+    LifecycleState::Suspended,
+    LifecycleState::Removed,
+];
+
+impl LifecycleState {
+    pub fn parse(s: &str) -> Option<Self> {
+        LIFECYCLE_STATES.iter().find(|v| v.as_str() == s).cloned()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleState::Active => "active",
+            LifecycleState::Warned => "warned",
+            LifecycleState::Suspended => "suspended",
+            LifecycleState::Removed => "removed",
+        }
+    }
+
+    // 暂停与移除的成员不进入任何榜单
+    pub fn listed(&self) -> bool {
+        matches!(self, LifecycleState::Active | LifecycleState::Warned)
+    }
+}
+
+// 各成员当前的状态，没有记录的成员为 Active
+pub type LifecycleStates = HashMap<i64, LifecycleState>;
+
+pub fn state_of(states: &LifecycleStates, member: &i64) -> LifecycleState {
+    states
+        .get(member)
+        .cloned()
+        .unwrap_or(LifecycleState::Active)
+}
+
+// 各状态的未活跃天数阈值，环境变量 LIFECYCLE_WARN_DAYS、LIFECYCLE_SUSPEND_DAYS、LIFECYCLE_REMOVE_DAYS
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LifecycleThresholds {
+    pub warn_days: i64,
+    pub suspend_days: i64,
+    pub remove_days: i64,
+}
+
+impl Default for LifecycleThresholds {
+    fn default() -> Self {
+        LifecycleThresholds {
+            warn_days: WARN_DAYS,
+            suspend_days: SUSPEND_DAYS,
+            remove_days: REMOVE_DAYS,
+        }
+    }
+}
+
+impl LifecycleThresholds {
+    // 阈值必须为正且依次增大，否则使用缺省值
+    pub fn from_env() -> Self {
+        let days = |key: &str, default: i64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse::<i64>().ok())
+                .unwrap_or(default)
+        };
+        let thresholds = LifecycleThresholds {
+            warn_days: days("LIFECYCLE_WARN_DAYS", WARN_DAYS),
+            suspend_days: days("LIFECYCLE_SUSPEND_DAYS", SUSPEND_DAYS),
+            remove_days: days("LIFECYCLE_REMOVE_DAYS", REMOVE_DAYS),
+        };
+        if 0 < thresholds.warn_days
+            && thresholds.warn_days < thresholds.suspend_days
+            && thresholds.suspend_days < thresholds.remove_days
+        {
+            thresholds
+        } else {
+            error!(
+                "invalid lifecycle thresholds {:?}, using defaults",
+                thresholds
+            );
+            Self::default()
+        }
+    }
+
+    // 恢复活跃后直接回到 active
+    pub fn state_for(&self, idle: i64) -> LifecycleState {
+        if idle >= self.remove_days {
+            LifecycleState::Removed
+        } else if idle >= self.suspend_days {
+            LifecycleState::Suspended
+        } else if idle >= self.warn_days {
+            LifecycleState::Warned
+        } else {
+            LifecycleState::Active
+        }
+    }
+}
+
+#[derive(Queryable, Debug, Clone, Serialize)]
+pub struct LifecycleTransition {
+    #[serde(skip_serializing)]
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub membership_id: i64,
+    pub from_state: String,
+    pub to_state: String,
+    pub idle_days: i32,
+    pub last_active_at: Option<NaiveDateTime>,
+}
+
+// 一次状态变化，写库与通知共用
+#[derive(Debug, Clone)]
+pub struct NewTransition {
+    pub membership_id: i64,
+    pub from: LifecycleState,
+    pub to: LifecycleState,
+    pub idle_days: i64,
+    pub last_active_at: Option<NaiveDateTime>,
+}
+
+impl LifecycleTransition {
+    // 各成员最近一次变化后的状态，没有记录的成员为 active
+    pub fn current_states(
+        conn: &mut SqliteConnection,
+    ) -> Result<HashMap<i64, LifecycleState>, anyhow::Error> {
+        let rows = lifecycle_transitions
+            .select((membership_id, to_state))
+            .order_by(created_at)
+            .then_order_by(id)
+            .load::<(i64, String)>(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(member, state)| LifecycleState::parse(&state).map(|s| (member, s)))
+            .collect())
+    }
+
+    pub fn of_member(
+        conn: &mut SqliteConnection,
+        member: i64,
+    ) -> Result<Vec<LifecycleTransition>, anyhow::Error> {
+        lifecycle_transitions
+            .filter(membership_id.eq(member))
+            .order_by(created_at)
+            .then_order_by(id)
+            .load::<LifecycleTransition>(conn)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    // start 之后的全部变化，按时间排列
+    pub fn since(
+        conn: &mut SqliteConnection,
+        start: NaiveDateTime,
+    ) -> Result<Vec<LifecycleTransition>, anyhow::Error> {
+        lifecycle_transitions
+            .filter(created_at.ge(start))
+            .order_by(created_at)
+            .then_order_by(id)
+            .load::<LifecycleTransition>(conn)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    pub fn record(
+        conn: &mut SqliteConnection,
+        at: NaiveDateTime,
+        rows: &[NewTransition],
+    ) -> Result<(), diesel::result::Error> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let values: Vec<_> = rows
+            .iter()
+            .map(|t| {
+                (
+                    created_at.eq(at),
+                    membership_id.eq(t.membership_id),
+                    from_state.eq(t.from.as_str()),
+                    to_state.eq(t.to.as_str()),
+                    idle_days.eq(t.idle_days as i32),
+                    last_active_at.eq(t.last_active_at),
+                )
+            })
+            .collect();
+        diesel::insert_into(lifecycle_transitions::table)
+            .values(&values)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    // 在同一事务中读取当前状态并写入变化，多个实例同时执行时不会重复记录
+    pub fn advance(
+        conn: &mut SqliteConnection,
+        thresholds: &LifecycleThresholds,
+        last_active: &HashMap<i64, NaiveDateTime>,
+        now: NaiveDateTime,
+    ) -> Result<(HashMap<i64, LifecycleState>, Vec<NewTransition>), anyhow::Error> {
+        conn.immediate_transaction(|conn| {
+            let mut states = Self::current_states(conn)?;
+            let mut changes: Vec<NewTransition> = last_active
+                .iter()
+                .filter_map(|(member, at)| {
+                    let idle = (now.date() - at.date()).num_days().max(0);
+                    let from = states
+                        .get(member)
+                        .cloned()
+                        .unwrap_or(LifecycleState::Active);
+                    let to = thresholds.state_for(idle);
+                    (from != to).then_some(NewTransition {
+                        membership_id: *member,
+                        from,
+                        to,
+                        idle_days: idle,
+                        last_active_at: Some(*at),
+                    })
+                })
+                .collect();
+            changes.sort_by_key(|t| t.membership_id);
+            Self::record(conn, now, &changes).map_err(|e| anyhow!("{:?}", e))?;
+            changes.iter().for_each(|t| {
+                states.insert(t.membership_id, t.to);
+            });
+            Ok((states, changes))
+        })
+    }
+}
+
+/// 状态变化的 webhook 请求体
+#[derive(Serialize, Debug, Clone)]
+pub struct LifecycleEvent {
+    pub domain: String,
+    pub name: String,
+    pub from: LifecycleState,
+    pub to: LifecycleState,
+    pub idle_days: i64,
+    pub last_active_at: Option<NaiveDateTime>,
+    pub at: NaiveDateTime,
+}
+
+// 逐条 POST JSON 到 LIFECYCLE_WEBHOOK_URL，失败只记日志，不重试
+pub async fn notify(url: &str, events: &[LifecycleEvent]) {
+    let client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!("lifecycle webhook client: {}", e);
+            return;
+        }
+    };
+    for event in events {
+        let res = client
+            .post(url)
+            .json(event)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = res {
+            error!("lifecycle webhook {}: {}", event.domain, e);
+        }
+    }
+}
@@ -1,14 +1,22 @@
+use std::{fs::File, io, path::PathBuf, str::FromStr};
+
 use axum::{extract::Extension, routing::get, Router};
-use chrono::{NaiveDateTime, NaiveTime};
+use chrono::NaiveDate;
+use clap::{Parser, Subcommand};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use domaincards::{
+    api_router::{
+        embed_snippets, export_statistics, levels_explain, lifecycle_members, member_stats,
+        rank_listing, referral_edges, review_members, shields_endpoint,
+    },
     app_model::{Context, DynContext},
     app_router::{
-        home_page, join_us_page, rank_page, show_badge, show_card, show_favicon, show_icon,
-        ws_upgrade,
+        go_member, home_page, join_us_page, rank_page, referrals_page, show_badge, show_card,
+        show_chart, show_favicon, show_heatmap, show_icon, ws_upgrade,
     },
-    establish_connection, now_shanghai,
-    statistics_model::Statistics,
+    establish_connection,
+    membership_model::{Membership, MEMBERSHIP_PATH},
+    statistics_io::{self, ConflictPolicy, ExportFilter, ExportFormat},
     DbPool,
 };
 use dotenv::dotenv;
@@ -18,10 +26,53 @@ use tower_http::services::{ServeDir, ServeFile};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations/");
 
+#[derive(Parser)]
+#[command(version, about = "米表联盟 domain.cards")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// 启动 Web 服务（默认）
+    Serve,
+    /// 导出日统计为 CSV 或 JSON Lines
+    Export {
+        /// 成员域名或 ID，缺省导出全部成员
+        #[arg(long)]
+        member: Option<String>,
+        /// 起始日期（含），YYYY-MM-DD
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// 结束日期（含），YYYY-MM-DD
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// csv 或 jsonl
+        #[arg(long, default_value = "csv")]
+        format: String,
+        /// 输出文件，缺省输出到标准输出
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// 将导出的文件合并回数据库
+    Import {
+        file: PathBuf,
+        /// csv 或 jsonl，缺省按扩展名推断
+        #[arg(long)]
+        format: Option<String>,
+        /// 相同成员同一天已有数据时：replace、skip、max 或 sum
+        #[arg(long, default_value = "replace")]
+        on_conflict: String,
+    },
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<(), anyhow::Error> {
     dotenv().ok();
-    tracing_subscriber::fmt::init();
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+
+    let cli = Cli::parse();
 
     let db_pool: DbPool = establish_connection(&env::var("DATABASE_URL").unwrap());
 
@@ -34,12 +85,61 @@ async fn main() {
             .unwrap()
     );
 
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(db_pool).await,
+        Command::Export {
+            member,
+            from,
+            to,
+            format,
+            output,
+        } => {
+            let format = ExportFormat::from_str(&format)?;
+            let members = Membership::load_all(MEMBERSHIP_PATH)?;
+            let membership_id = match member {
+                Some(key) => Some(
+                    statistics_io::find_member(&members, &key)
+                        .ok_or_else(|| anyhow::anyhow!("member {} not found", key))?
+                        .id,
+                ),
+                None => None,
+            };
+            let filter = ExportFilter::new(membership_id, from, to);
+            let count = match output {
+                Some(path) => {
+                    statistics_io::export(&db_pool, &members, &filter, format, File::create(path)?)?
+                }
+                None => statistics_io::export(&db_pool, &members, &filter, format, io::stdout())?,
+            };
+            tracing::info!("exported {} rows", count);
+        }
+        Command::Import {
+            file,
+            format,
+            on_conflict,
+        } => {
+            let format = match format {
+                Some(format) => ExportFormat::from_str(&format)?,
+                None => ExportFormat::from_path(&file.to_string_lossy())
+                    .ok_or_else(|| anyhow::anyhow!("cannot infer format, use --format"))?,
+            };
+            let policy = ConflictPolicy::from_str(&on_conflict)?;
+            let members = Membership::load_all(MEMBERSHIP_PATH)?;
+            let summary =
+                statistics_io::import(&db_pool, &members, format, File::open(file)?, policy)?;
+            tracing::info!("import {:?}", summary);
+        }
+    }
+    Ok(())
+}
+
+async fn serve(db_pool: DbPool) {
     let context = Arc::new(Context::default(db_pool).await) as DynContext;
 
     // 定时存入数据库
     let ctx_clone = context.clone();
     tokio::spawn(async move {
-        ctx_clone.save_per_5_minutes().await;
+        ctx_clone.run_scheduler().await;
     });
 
     let ctx_clone_for_shutdown = context.clone();
@@ -53,13 +153,26 @@ async fn main() {
             Router::new()
                 .route("/badge/:domain", get(show_badge))
                 .route("/card/:domain", get(show_card))
+                .route("/export", get(export_statistics))
+                .route("/stats/:domain", get(member_stats))
+                .route("/levels", get(levels_explain))
+                .route("/review", get(review_members))
+                .route("/lifecycle", get(lifecycle_members))
+                .route("/referrals", get(referral_edges))
+                .route("/rank", get(rank_listing))
                 .route("/favicon/:domain", get(show_favicon))
                 .route("/icon/:domain", get(show_icon))
+                .route("/shields/:domain", get(shields_endpoint))
+                .route("/heatmap/:domain", get(show_heatmap))
+                .route("/chart/:domain", get(show_chart))
+                .route("/embed/:domain", get(embed_snippets))
                 .route("/ws", get(ws_upgrade)),
         )
         .route("/", get(home_page))
         .route("/join-us", get(join_us_page))
         .route("/rank", get(rank_page))
+        .route("/referrals", get(referrals_page))
+        .route("/go/:domain", get(go_member))
         .nest_service(
             "/avatar",
             avatar_dir.not_found_service(ServeFile::new("templates/assets/img/logo.svg")),
@@ -67,7 +180,11 @@ async fn main() {
         .nest_service("/assets", asset_dir)
         .layer(Extension(context));
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let port = env::var("PORT")
+        .ok()
+        .and_then(|p| p.parse::<u16>().ok())
+        .unwrap_or(3000);
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
     tracing::debug!("listening on {}", addr);
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app)
@@ -101,28 +218,5 @@ async fn shutdown_signal(ctx: Arc<Context>) {
 
     println!("signal received, running cleanup tasks..");
 
-    let _today = NaiveDateTime::new(now_shanghai().date(), NaiveTime::from_hms(0, 0, 0));
-    let page_view_read = ctx.unique_visitor.read().await;
-    let referrer_read = ctx.referrer.read().await;
-    ctx.id2member.keys().for_each(|id| {
-        let uv = *page_view_read
-            .get(id)
-            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-        let referrer = *referrer_read
-            .get(id)
-            .unwrap_or(&(0, NaiveDateTime::from_timestamp(0, 0)));
-        Statistics::insert_or_update(
-            ctx.db_pool.get().unwrap(),
-            &Statistics {
-                created_at: _today,
-                membership_id: *id,
-                unique_visitor: uv.0,
-                updated_at: uv.1,
-                referrer: referrer.0,
-                latest_referrer_at: Some(referrer.1),
-                id: 0,
-            },
-        )
-        .unwrap();
-    })
+    ctx.flush().await;
 }
@@ -1,6 +1,11 @@
+use std::{collections::HashMap, fs};
+
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
-use crate::statistics_model::Statistics;
+use crate::{i18n::Lang, rank_history_model::RankMovement, statistics_model::Statistics};
+
+pub const MEMBERSHIP_PATH: &str = "./resources/membership.json";
 
 #[derive(Deserialize, Clone, Debug, Serialize)]
 pub struct Membership {
@@ -11,10 +16,57 @@ pub struct Membership {
     pub description: String,
     pub github_username: String,
     pub hidden: Option<bool>,
+    // 加入日期，未填写时取第一条统计的日期
+    pub joined_at: Option<NaiveDate>,
+    // 徽章、卡片、图标的缺省主题（light、dark、auto）与强调色，可被查询参数覆盖
+    pub theme: Option<String>,
+    pub accent: Option<String>,
+    // 其他语言的名称与简介，键为 zh、en；缺少的项使用 name、description
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub locales: HashMap<String, LocalizedMember>,
+}
+
+#[derive(Deserialize, Clone, Debug, Serialize, Default)]
+pub struct LocalizedMember {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Membership {
+    // 读取成员列表（包含隐藏成员），并将 ID 补给 member
+    pub fn load_all(path: &str) -> Result<HashMap<i64, Membership>, anyhow::Error> {
+        let mut membership: HashMap<i64, Membership> =
+            serde_json::from_str(&fs::read_to_string(path)?)?;
+        membership.iter_mut().for_each(|(k, v)| v.id = *k);
+        Ok(membership)
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden.unwrap_or(false)
+    }
+
+    fn localized(&self, lang: Lang) -> Option<&LocalizedMember> {
+        self.locales.get(lang.as_str())
+    }
+
+    pub fn name_in(&self, lang: Lang) -> &str {
+        self.localized(lang)
+            .and_then(|l| l.name.as_deref())
+            .filter(|v| !v.is_empty())
+            .unwrap_or(&self.name)
+    }
+
+    pub fn description_in(&self, lang: Lang) -> &str {
+        self.localized(lang)
+            .and_then(|l| l.description.as_deref())
+            .filter(|v| !v.is_empty())
+            .unwrap_or(&self.description)
+    }
 }
 
 #[derive(Deserialize, Clone, Serialize)]
 pub struct RankAndMembership {
     pub rank: Statistics,
     pub membership: Membership,
+    pub movement: Option<RankMovement>,
 }
@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use axum::body::Bytes;
+use lazy_static::lazy_static;
+use resvg::{tiny_skia, usvg};
+use tracing::info;
+
+use crate::{i18n::Lang, svg_template::Theme};
+
+// 支持的最大倍率，对应 @2x
+const PNG_MAX_SCALE: u32 = 2;
+// 缓存条目的上限，超出时清空重建
+const PNG_CACHE_CAPACITY: usize = 4096;
+
+// 按顺序选用第一个已安装的字体作为 sans-serif
+const SANS_SERIF_FAMILIES: [&str; 6] = [
+    "Noto Sans CJK SC",
+    "Source Han Sans SC",
+    "WenQuanYi Micro Hei",
+    "Noto Sans",
+    "DejaVu Sans",
+    "Ubuntu",
+];
+
+lazy_static! {
+    // 内置 Ubuntu 字体，再加上系统字体与 PNG_FONT_DIR 中的字体，用于中文等内置字体缺少的字形
+    static ref FONT_DB: Arc<usvg::fontdb::Database> = {
+        let mut db = usvg::fontdb::Database::new();
+        db.load_font_data(std::fs::read("templates/assets/fonts/ubuntu.ttf").unwrap());
+        db.load_system_fonts();
+        if let Ok(dir) = std::env::var("PNG_FONT_DIR") {
+            db.load_fonts_dir(dir);
+        }
+        // 内置字体只是域名与数字用到的子集，sans-serif 优先使用主机上较完整的字体
+        let sans = SANS_SERIF_FAMILIES
+            .iter()
+            .find(|family| {
+                db.faces()
+                    .any(|face| face.families.iter().any(|(name, _)| name == *family))
+            })
+            .unwrap_or(&"Ubuntu");
+        db.set_sans_serif_family(*sans);
+        info!("png renderer loaded {} font faces", db.len());
+        Arc::new(db)
+    };
+}
+
+// 路径末尾为 .png 或 @2x.png 时返回去掉后缀的域名与倍率
+pub fn split_png(path: &str) -> (&str, Option<u32>) {
+    let Some(name) = path.strip_suffix(".png") else {
+        return (path, None);
+    };
+    for scale in 2..=PNG_MAX_SCALE {
+        if let Some(name) = name.strip_suffix(&format!("@{}x", scale)) {
+            return (name, Some(scale));
+        }
+    }
+    (name, Some(1))
+}
+
+// 在 CPU 上按倍率栅格化
+pub fn rasterize(svg: &str, scale: u32) -> Result<Vec<u8>, anyhow::Error> {
+    let opt = usvg::Options {
+        fontdb: FONT_DB.clone(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_str(svg, &opt).map_err(|e| anyhow!("parse svg: {}", e))?;
+    let size = tree
+        .size()
+        .to_int_size()
+        .scale_by(scale as f32)
+        .ok_or_else(|| anyhow!("invalid png size"))?;
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| anyhow!("invalid png size"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale as f32, scale as f32),
+        &mut pixmap.as_mut(),
+    );
+    pixmap
+        .encode_png()
+        .map_err(|e| anyhow!("encode png: {}", e))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PngKey {
+    // badge、card、icon
+    kind: &'static str,
+    member: i64,
+    scale: u32,
+    theme: String,
+    lang: Lang,
+}
+
+impl PngKey {
+    pub fn new(kind: &'static str, member: i64, scale: u32, theme: &Theme, lang: Lang) -> Self {
+        PngKey {
+            kind,
+            member,
+            scale,
+            theme: format!("{}{}", theme.mode.as_str(), theme.accent),
+            lang,
+        }
+    }
+}
+
+// 图片上的计数：独立访客、referrer、等级
+pub type CounterState = (i64, i64, i64);
+
+// 渲染好的 PNG，每个成员、种类、倍率、主题与语言只保留最新计数对应的一份
+#[derive(Default)]
+pub struct PngCache {
+    entries: Mutex<HashMap<PngKey, (CounterState, Bytes)>>,
+}
+
+impl PngCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 头像等计数以外的内容变化、或跨天时清空
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    // 计数没变时直接返回缓存，否则渲染 SVG 并在阻塞线程中栅格化
+    pub async fn get_or_render<F>(
+        &self,
+        key: PngKey,
+        state: CounterState,
+        svg: F,
+    ) -> Result<Bytes, anyhow::Error>
+    where
+        F: FnOnce() -> Result<String, askama::Error>,
+    {
+        if let Some((cached, png)) = self.entries.lock().unwrap().get(&key) {
+            if *cached == state {
+                return Ok(png.clone());
+            }
+        }
+        let svg = svg().map_err(|e| anyhow!("render svg: {}", e))?;
+        let scale = key.scale;
+        let png = Bytes::from(
+            tokio::task::spawn_blocking(move || rasterize(&svg, scale))
+                .await
+                .map_err(|e| anyhow!("{:?}", e))??,
+        );
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= PNG_CACHE_CAPACITY {
+            entries.clear();
+        }
+        entries.insert(key, (state, png.clone()));
+        Ok(png)
+    }
+}
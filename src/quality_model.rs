@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use crate::fnv1a;
+use crate::schema::visit_diversity::{self, dsl::*};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel::SqliteConnection;
+use serde::Serialize;
+
+// 单个网段计入的访问最多占总访问的比例
+pub const MAX_NETWORK_SHARE: f64 = 0.2;
+// 访问数不少于该值时才会被标记待审核
+pub const REVIEW_MIN_VISITS: i64 = 20;
+// 标记阈值：最大网段占比、加权后保留的比例
+const REVIEW_NETWORK_SHARE: f64 = 0.5;
+const REVIEW_WEIGHTED_RATIO: f64 = 0.3;
+
+pub const DIMENSION_NETWORK: &str = "network";
+pub const DIMENSION_COUNTRY: &str = "country";
+pub const DIMENSION_USER_AGENT: &str = "user_agent";
+
+// 同一网段：IPv4 取 /24，IPv6 取 /48；没有可用的 IP（未经 Cloudflare）时返回 None
+pub fn network_of(ip: &str) -> Option<String> {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => {
+            let o = v4.octets();
+            Some(format!("{}.{}.{}.0/24", o[0], o[1], o[2]))
+        }
+        Ok(IpAddr::V6(v6)) => {
+            let s = v6.segments();
+            Some(format!("{:x}:{:x}:{:x}::/48", s[0], s[1], s[2]))
+        }
+        Err(_) => None,
+    }
+}
+
+// CF-IPCountry 为两位国家代码，缺失或无法识别时记为 XX（与 Cloudflare 一致）
+pub fn country_code(header: &str) -> String {
+    let code = header.trim().to_ascii_uppercase();
+    if code.len() == 2 && code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        code
+    } else {
+        "XX".to_string()
+    }
+}
+
+// 只保存 User-Agent 的摘要，使用 FNV-1a 64 位，不随 Rust 版本或实例变化
+pub fn user_agent_digest(user_agent: &str) -> String {
+    format!("{:016x}", fnv1a(user_agent.as_bytes()))
+}
+
+// 一次计数的访问在各维度上的取值
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DiversityKey {
+    pub membership_id: i64,
+    pub visitor_type: i32,
+    pub dimension: &'static str,
+    pub value: String,
+}
+
+// 成员 id 到访问质量
+pub type QualityMap = HashMap<i64, QualityScore>;
+
+// 各成员按国家的访问数
+pub type CountryCounts = Vec<CountryCount>;
+
+// 某个成员在某个国家的访问，访客合并徽章、卡片、图标等
+#[derive(Serialize, Debug, Clone)]
+pub struct CountryCount {
+    #[serde(skip_serializing)]
+    pub membership_id: i64,
+    pub country: String,
+    pub unique_visitor: i64,
+    pub referrer: i64,
+}
+
+pub struct VisitDiversity;
+
+impl VisitDiversity {
+    // 把当天各来源维度的访问数累加到 (成员, 日期, 访问类型, 维度, 取值) 上
+    pub fn increment_all(
+        conn: &mut SqliteConnection,
+        day: NaiveDateTime,
+        rows: &HashMap<DiversityKey, i64>,
+    ) -> Result<(), diesel::result::Error> {
+        conn.transaction(|conn| {
+            for (key, delta) in rows {
+                diesel::insert_into(visit_diversity::table)
+                    .values((
+                        created_at.eq(day),
+                        membership_id.eq(key.membership_id),
+                        visitor_type.eq(key.visitor_type),
+                        dimension.eq(key.dimension),
+                        value.eq(&key.value),
+                        count.eq(delta),
+                    ))
+                    .on_conflict((membership_id, created_at, visitor_type, dimension, value))
+                    .do_update()
+                    .set(count.eq(count + excluded(count)))
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    // 日期区间内各成员按国家的访问数，按访问总数从多到少、国家代码排列
+    pub fn countries_between(
+        conn: &mut SqliteConnection,
+        member: Option<i64>,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<CountryCounts, anyhow::Error> {
+        let mut query = visit_diversity
+            .select((
+                membership_id,
+                value,
+                visitor_type,
+                sql::<diesel::sql_types::BigInt>("SUM(count)"),
+            ))
+            .filter(created_at.between(start, end))
+            .filter(dimension.eq(DIMENSION_COUNTRY))
+            .group_by((membership_id, value, visitor_type))
+            .into_boxed();
+        if let Some(member) = member {
+            query = query.filter(membership_id.eq(member));
+        }
+        let rows = query
+            .load::<(i64, String, i32, i64)>(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        let mut counts: HashMap<(i64, String), (i64, i64)> = HashMap::new();
+        rows.into_iter().for_each(|(member, country, vt, c)| {
+            // 上线前的记录可能是空值
+            let entry = counts.entry((member, country_code(&country))).or_default();
+            if vt == REFERRER_TYPE {
+                entry.1 += c;
+            } else {
+                entry.0 += c;
+            }
+        });
+        let mut res: CountryCounts = counts
+            .into_iter()
+            .map(|((member, country), (uv, rv))| CountryCount {
+                membership_id: member,
+                country,
+                unique_visitor: uv,
+                referrer: rv,
+            })
+            .collect();
+        res.sort_by(|a, b| {
+            (b.unique_visitor + b.referrer)
+                .cmp(&(a.unique_visitor + a.referrer))
+                .then(a.country.cmp(&b.country))
+                .then(a.membership_id.cmp(&b.membership_id))
+        });
+        Ok(res)
+    }
+
+    // 日期区间内各成员的访问质量
+    pub fn quality_between(
+        conn: &mut SqliteConnection,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<QualityMap, anyhow::Error> {
+        let rows = visit_diversity
+            .select((
+                membership_id,
+                visitor_type,
+                dimension,
+                value,
+                sql::<diesel::sql_types::BigInt>("SUM(count)"),
+            ))
+            .filter(created_at.between(start, end))
+            .group_by((membership_id, visitor_type, dimension, value))
+            .load::<(i64, i32, String, String, i64)>(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+
+        // 徽章、卡片、图标等合并为访客
+        let mut counts: HashMap<(i64, bool), DimensionCounts> = HashMap::new();
+        rows.into_iter().for_each(|(member, vt, dim, v, c)| {
+            let entry = counts.entry((member, vt == REFERRER_TYPE)).or_default();
+            match dim.as_str() {
+                DIMENSION_NETWORK => {
+                    *entry.networks.entry(v).or_default() += c;
+                }
+                DIMENSION_COUNTRY => {
+                    entry.countries.insert(v);
+                }
+                DIMENSION_USER_AGENT => {
+                    entry.user_agents.insert(v);
+                }
+                _ => {}
+            }
+        });
+
+        let mut res = QualityMap::new();
+        counts.into_iter().for_each(|((member, is_referrer), c)| {
+            let score = res.entry(member).or_default();
+            if is_referrer {
+                score.referrer = c.quality();
+            } else {
+                score.visitor = c.quality();
+            }
+        });
+        res.values_mut().for_each(|s| s.flags = s.review_flags());
+        Ok(res)
+    }
+}
+
+// VisitorType::Referer
+pub const REFERRER_TYPE: i32 = 1;
+
+#[derive(Default)]
+struct DimensionCounts {
+    networks: HashMap<String, i64>,
+    countries: HashSet<String>,
+    user_agents: HashSet<String>,
+}
+
+impl DimensionCounts {
+    // weighted = Σ min(网段访问数, ceil(MAX_NETWORK_SHARE × 总数)) × (0.6 + 0.3 × UA 比例 + 0.1 × 国家比例)，
+    // UA 比例 = min(1, 不同 UA 数 / min(总数, 5))，国家比例 = min(1, 不同国家数 / min(总数, 2))
+    fn quality(&self) -> VisitQuality {
+        let visits: i64 = self.networks.values().sum();
+        if visits == 0 {
+            return VisitQuality::default();
+        }
+        let cap = ((MAX_NETWORK_SHARE * visits as f64).ceil() as i64).max(1);
+        let capped: i64 = self.networks.values().map(|c| (*c).min(cap)).sum();
+        let ratio = |distinct: usize, expected: i64| {
+            (distinct as f64 / visits.min(expected) as f64).min(1.0)
+        };
+        let factor =
+            0.6 + 0.3 * ratio(self.user_agents.len(), 5) + 0.1 * ratio(self.countries.len(), 2);
+        VisitQuality {
+            visits,
+            networks: self.networks.len(),
+            countries: self.countries.len(),
+            user_agents: self.user_agents.len(),
+            top_network_share: *self.networks.values().max().unwrap() as f64 / visits as f64,
+            weighted: capped as f64 * factor,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VisitQuality {
+    pub visits: i64,
+    pub networks: usize,
+    pub countries: usize,
+    pub user_agents: usize,
+    pub top_network_share: f64,
+    pub weighted: f64,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct QualityScore {
+    pub visitor: VisitQuality,
+    pub referrer: VisitQuality,
+    pub flags: Vec<String>,
+}
+
+impl QualityScore {
+    // 没有来源记录的访问（上线前的历史数据）按原值计入
+    pub fn weighted_visitor(&self, raw: i64) -> f64 {
+        self.visitor.weighted + (raw - self.visitor.visits).max(0) as f64
+    }
+
+    pub fn weighted_referrer(&self, raw: i64) -> f64 {
+        self.referrer.weighted + (raw - self.referrer.visits).max(0) as f64
+    }
+
+    fn review_flags(&self) -> Vec<String> {
+        let mut flags = Vec::new();
+        for (kind, q) in [("referrer", &self.referrer), ("visitor", &self.visitor)] {
+            if q.visits < REVIEW_MIN_VISITS {
+                continue;
+            }
+            if q.top_network_share > REVIEW_NETWORK_SHARE {
+                flags.push(format!("{}_network_share", kind));
+            }
+            if q.user_agents <= 1 {
+                flags.push(format!("{}_single_user_agent", kind));
+            }
+            if q.weighted / (q.visits as f64) < REVIEW_WEIGHTED_RATIO {
+                flags.push(format!("{}_low_diversity", kind));
+            }
+        }
+        flags
+    }
+
+    pub fn suspicious(&self) -> bool {
+        !self.flags.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_of_groups_by_prefix() {
+        assert_eq!(network_of("1.2.3.4").as_deref(), Some("1.2.3.0/24"));
+        assert_eq!(
+            network_of("2001:db8:1:2::1").as_deref(),
+            Some("2001:db8:1::/48")
+        );
+        assert_eq!(network_of(""), None);
+        assert_eq!(network_of("unknown"), None);
+    }
+
+    // 摘要写入数据库，实例之间、升级前后必须一致
+    #[test]
+    fn user_agent_digest_is_stable() {
+        assert_eq!(user_agent_digest(""), "cbf29ce484222325");
+        assert_eq!(user_agent_digest("a"), "af63dc4c8601ec8c");
+        assert_eq!(user_agent_digest("foobar"), "85944171f73967e8");
+    }
+
+    fn counts(networks: &[(&str, i64)], user_agents: usize, countries: usize) -> DimensionCounts {
+        DimensionCounts {
+            networks: networks.iter().map(|(n, c)| (n.to_string(), *c)).collect(),
+            countries: (0..countries).map(|i| format!("C{}", i)).collect(),
+            user_agents: (0..user_agents).map(|i| format!("ua{}", i)).collect(),
+        }
+    }
+
+    #[test]
+    fn quality_without_networks_is_empty() {
+        let q = counts(&[], 3, 2).quality();
+        assert_eq!(q.visits, 0);
+        assert_eq!(q.weighted, 0.0);
+    }
+
+    #[test]
+    fn quality_keeps_diverse_visits() {
+        let q = counts(&[("a", 2), ("b", 2), ("c", 2), ("d", 2), ("e", 2)], 5, 2).quality();
+        assert_eq!(q.visits, 10);
+        assert_eq!(q.networks, 5);
+        assert!((q.top_network_share - 0.2).abs() < 1e-9);
+        assert!((q.weighted - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quality_caps_share_of_single_network() {
+        // 10 次访问，上限 ceil(0.2 × 10) = 2；因子 0.6 + 0.3 × 1/5 + 0.1 × 1/2 = 0.71
+        let q = counts(&[("a", 10)], 1, 1).quality();
+        assert_eq!(q.top_network_share, 1.0);
+        assert!((q.weighted - 2.0 * 0.71).abs() < 1e-9);
+
+        // 访问很少时上限至少为 1
+        let q = counts(&[("a", 3)], 3, 2).quality();
+        assert!((q.weighted - 1.0).abs() < 1e-9);
+        let q = counts(&[("a", 8), ("b", 1), ("c", 1)], 5, 2).quality();
+        assert!((q.weighted - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn review_flags_need_enough_visits() {
+        let mut score = QualityScore {
+            referrer: counts(&[("a", REVIEW_MIN_VISITS - 1)], 1, 1).quality(),
+            ..Default::default()
+        };
+        assert!(score.review_flags().is_empty());
+
+        score.referrer = counts(&[("a", REVIEW_MIN_VISITS)], 1, 0).quality();
+        assert_eq!(
+            score.review_flags(),
+            [
+                "referrer_network_share",
+                "referrer_single_user_agent",
+                "referrer_low_diversity"
+            ]
+        );
+    }
+
+    #[test]
+    fn review_flags_ignore_diverse_visits() {
+        let networks: Vec<(String, i64)> = (0..10).map(|i| (format!("n{}", i), 3)).collect();
+        let networks: Vec<(&str, i64)> = networks.iter().map(|(n, c)| (n.as_str(), *c)).collect();
+        let score = QualityScore {
+            visitor: counts(&networks, 5, 2).quality(),
+            referrer: counts(&networks, 5, 2).quality(),
+            ..Default::default()
+        };
+        assert!(score.review_flags().is_empty());
+
+        // 访客只有一个 UA，其他指标正常
+        let score = QualityScore {
+            visitor: counts(&networks, 1, 2).quality(),
+            ..score
+        };
+        assert_eq!(score.review_flags(), ["visitor_single_user_agent"]);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use tokio::sync::RwLock;
+
+use crate::{
+    clock::start_of_day,
+    quality_model::{CountryCounts, QualityMap, VisitDiversity},
+    statistics_model::Statistics,
+    DbPool,
+};
+
+// 最多缓存的区间数，超出时淘汰最早计算的
+const RANK_CACHE_CAPACITY: usize = 64;
+// 自定义区间的最大跨度
+const CUSTOM_RANGE_MAX_DAYS: i64 = 366 * 3;
+
+// 按日期区间汇总的榜单
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RankPeriod {
+    // 最近 7 天
+    Weekly,
+    // 最近 30 天
+    Monthly,
+    // 本自然月
+    CalendarMonth,
+    // 本自然年
+    Yearly,
+    AllTime,
+    // 日期闭区间
+    Custom(NaiveDate, NaiveDate),
+}
+
+impl RankPeriod {
+    // 解析 rank_type，custom 需要 from 与 to
+    pub fn parse(
+        rank_type: &str,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Option<RankPeriod>, anyhow::Error> {
+        Ok(Some(match rank_type {
+            "weekly" => RankPeriod::Weekly,
+            "monthly" => RankPeriod::Monthly,
+            "calendar_month" => RankPeriod::CalendarMonth,
+            "yearly" => RankPeriod::Yearly,
+            "all" => RankPeriod::AllTime,
+            "custom" => match (from, to) {
+                (Some(from), Some(to))
+                    if from <= to && (to - from).num_days() < CUSTOM_RANGE_MAX_DAYS =>
+                {
+                    RankPeriod::Custom(from, to)
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "custom range requires from <= to spanning at most {} days",
+                        CUSTOM_RANGE_MAX_DAYS
+                    ))
+                }
+            },
+            _ => return Ok(None),
+        }))
+    }
+
+    // 区间首尾两天（含）
+    pub fn days(&self, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+        match self {
+            RankPeriod::Weekly => (today - Duration::days(6), today),
+            RankPeriod::Monthly => (today - Duration::days(29), today),
+            RankPeriod::CalendarMonth => (today.with_day(1).unwrap(), today),
+            RankPeriod::Yearly => (today.with_ordinal(1).unwrap(), today),
+            RankPeriod::AllTime => (NaiveDateTime::from_timestamp(0, 0).date(), today),
+            RankPeriod::Custom(from, to) => (*from, *to),
+        }
+    }
+}
+
+struct CachedRank {
+    computed_at: NaiveDateTime,
+    rank: Arc<Vec<Statistics>>,
+}
+
+// rank_between 的结果按区间缓存，新增区间不需要在 Context 上增加字段
+pub struct RankCache {
+    entries: RwLock<HashMap<(NaiveDate, NaiveDate), CachedRank>>,
+    // 同一区间的访问质量，随排行一起清空
+    quality: RwLock<HashMap<(NaiveDate, NaiveDate), Arc<QualityMap>>>,
+    // 同一区间各成员按国家的访问数
+    countries: RwLock<HashMap<(NaiveDate, NaiveDate), Arc<CountryCounts>>>,
+}
+
+impl Default for RankCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RankCache {
+    pub fn new() -> Self {
+        RankCache {
+            entries: RwLock::new(HashMap::new()),
+            quality: RwLock::new(HashMap::new()),
+            countries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(
+        &self,
+        db_pool: &DbPool,
+        period: RankPeriod,
+        now: NaiveDateTime,
+    ) -> Result<Arc<Vec<Statistics>>, anyhow::Error> {
+        let key = period.days(now.date());
+        if let Some(cached) = self.entries.read().await.get(&key) {
+            return Ok(cached.rank.clone());
+        }
+        self.compute(db_pool, key, now).await
+    }
+
+    async fn compute(
+        &self,
+        db_pool: &DbPool,
+        key: (NaiveDate, NaiveDate),
+        now: NaiveDateTime,
+    ) -> Result<Arc<Vec<Statistics>>, anyhow::Error> {
+        let rank = Arc::new(Statistics::rank_between(
+            db_pool.get()?,
+            start_of_day(key.0),
+            start_of_day(key.1),
+        )?);
+        let mut entries = self.entries.write().await;
+        if entries.len() >= RANK_CACHE_CAPACITY && !entries.contains_key(&key) {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, v)| v.computed_at)
+                .map(|(k, _)| *k)
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CachedRank {
+                computed_at: now,
+                rank: rank.clone(),
+            },
+        );
+        Ok(rank)
+    }
+
+    // 日期闭区间内各成员的访问质量
+    pub async fn quality(
+        &self,
+        db_pool: &DbPool,
+        key: (NaiveDate, NaiveDate),
+    ) -> Result<Arc<QualityMap>, anyhow::Error> {
+        if let Some(cached) = self.quality.read().await.get(&key) {
+            return Ok(cached.clone());
+        }
+        let quality = Arc::new(VisitDiversity::quality_between(
+            &mut *db_pool.get()?,
+            start_of_day(key.0),
+            start_of_day(key.1),
+        )?);
+        let mut entries = self.quality.write().await;
+        if entries.len() >= RANK_CACHE_CAPACITY {
+            entries.clear();
+        }
+        entries.insert(key, quality.clone());
+        Ok(quality)
+    }
+
+    // 日期闭区间内各成员按国家的访问数
+    pub async fn countries(
+        &self,
+        db_pool: &DbPool,
+        key: (NaiveDate, NaiveDate),
+    ) -> Result<Arc<CountryCounts>, anyhow::Error> {
+        if let Some(cached) = self.countries.read().await.get(&key) {
+            return Ok(cached.clone());
+        }
+        let countries = Arc::new(VisitDiversity::countries_between(
+            &mut *db_pool.get()?,
+            None,
+            start_of_day(key.0),
+            start_of_day(key.1),
+        )?);
+        let mut entries = self.countries.write().await;
+        if entries.len() >= RANK_CACHE_CAPACITY {
+            entries.clear();
+        }
+        entries.insert(key, countries.clone());
+        Ok(countries)
+    }
+
+    // 写库后丢弃旧结果，并预先计算常用榜单
+    pub async fn refresh(&self, db_pool: &DbPool, now: NaiveDateTime, warm: &[RankPeriod]) {
+        self.entries.write().await.clear();
+        self.quality.write().await.clear();
+        self.countries.write().await.clear();
+        for period in warm {
+            if let Err(e) = self.compute(db_pool, period.days(now.date()), now).await {
+                tracing::error!("rank {:?}: {}", period, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use crate::schema::rank_history::{self, dsl::*};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::SqliteConnection;
+
+// 每天收盘后记录名次的榜单，随机与自定义区间不记录
+pub const SNAPSHOT_LISTS: [&str; 6] = [
+    "daily",
+    "weekly",
+    "monthly",
+    "calendar_month",
+    "yearly",
+    "all",
+];
+
+#[derive(Queryable, Debug, Clone, serde::Serialize)]
+pub struct RankHistory {
+    pub id: i32,
+    pub created_at: NaiveDateTime,
+    pub list: String,
+    pub membership_id: i64,
+    pub position: i32,
+}
+
+impl RankHistory {
+    // 覆盖写入某一天某个榜单的全部名次（从 1 开始），重复执行结果一致
+    pub fn record(
+        conn: &mut SqliteConnection,
+        _list: &str,
+        _created_at: NaiveDateTime,
+        ranked: &[i64],
+    ) -> Result<(), diesel::result::Error> {
+        conn.transaction(|conn| {
+            diesel::delete(rank_history.filter(list.eq(_list).and(created_at.eq(_created_at))))
+                .execute(conn)?;
+            if ranked.is_empty() {
+                return Ok(());
+            }
+            let rows: Vec<_> = ranked
+                .iter()
+                .enumerate()
+                .map(|(i, member)| {
+                    (
+                        created_at.eq(_created_at),
+                        list.eq(_list),
+                        membership_id.eq(*member),
+                        position.eq(i as i32 + 1),
+                    )
+                })
+                .collect();
+            diesel::insert_into(rank_history::table)
+                .values(&rows)
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    pub fn exists(
+        conn: &mut SqliteConnection,
+        _created_at: NaiveDateTime,
+    ) -> Result<bool, anyhow::Error> {
+        let count: i64 = rank_history
+            .filter(created_at.eq(_created_at))
+            .count()
+            .get_result(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(count > 0)
+    }
+
+    // 早于 before 的最近一次快照中各成员的名次
+    pub fn latest_before(
+        conn: &mut SqliteConnection,
+        _list: &str,
+        before: NaiveDateTime,
+    ) -> Result<HashMap<i64, i32>, anyhow::Error> {
+        let latest: Option<NaiveDateTime> = rank_history
+            .select(diesel::dsl::max(created_at))
+            .filter(list.eq(_list).and(created_at.lt(before)))
+            .first(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let latest = match latest {
+            Some(latest) => latest,
+            None => return Ok(HashMap::new()),
+        };
+        let rows = rank_history
+            .select((membership_id, position))
+            .filter(list.eq(_list).and(created_at.eq(latest)))
+            .load::<(i64, i32)>(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(rows.into_iter().collect())
+    }
+
+    // 成员在日期区间内的名次记录，按日期、榜单排序
+    pub fn of_member(
+        conn: &mut SqliteConnection,
+        member: i64,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<RankHistory>, anyhow::Error> {
+        rank_history
+            .filter(membership_id.eq(member).and(created_at.between(start, end)))
+            .order_by(created_at)
+            .then_order_by(list)
+            .load::<RankHistory>(conn)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+// 与上一次快照相比的名次变化
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", content = "delta", rename_all = "snake_case")]
+pub enum RankMovement {
+    New,
+    Up(i32),
+    Down(i32),
+    Same,
+}
+
+impl RankMovement {
+    pub fn of(previous: Option<i32>, current: i32) -> Self {
+        match previous {
+            None => RankMovement::New,
+            Some(p) if p > current => RankMovement::Up(p - current),
+            Some(p) if p < current => RankMovement::Down(current - p),
+            Some(_) => RankMovement::Same,
+        }
+    }
+
+    pub fn class(&self) -> &'static str {
+        match self {
+            RankMovement::New => "new",
+            RankMovement::Up(_) => "up",
+            RankMovement::Down(_) => "down",
+            RankMovement::Same => "same",
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            RankMovement::New => "NEW".to_string(),
+            RankMovement::Up(n) => format!("↑{}", n),
+            RankMovement::Down(n) => format!("↓{}", n),
+            RankMovement::Same => "–".to_string(),
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::{
+    app_model::Context, membership_model::RankAndMembership, rank_cache::RankPeriod,
+    rank_history_model::RankMovement, rank_strategy::last_active,
+};
+
+pub const DEFAULT_PER_PAGE: usize = 30;
+pub const MAX_PER_PAGE: usize = 100;
+const MAX_SEARCH_CHARS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    // 排行榜默认顺序
+    Rank,
+    Referrer,
+    Visitor,
+    LastActive,
+    Joined,
+    Name,
+}
+
+pub const SORT_KEYS: [SortKey; 6] = [
+    SortKey::Rank,
+    SortKey::Referrer,
+    SortKey::Visitor,
+    SortKey::LastActive,
+    SortKey::Joined,
+    SortKey::Name,
+];
+
+impl SortKey {
+    pub fn parse(s: &str) -> Option<Self> {
+        SORT_KEYS.iter().find(|k| k.as_str() == s).cloned()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Rank => "rank",
+            SortKey::Referrer => "referrer",
+            SortKey::Visitor => "uv",
+            SortKey::LastActive => "last_active",
+            SortKey::Joined => "joined",
+            SortKey::Name => "name",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortKey::Rank => "排名",
+            SortKey::Referrer => "RV",
+            SortKey::Visitor => "UV",
+            SortKey::LastActive => "最近活跃",
+            SortKey::Joined => "加入时间",
+            SortKey::Name => "名称",
+        }
+    }
+
+    // 数值与时间默认从大到小，排名与名称从小到大
+    pub fn default_desc(&self) -> bool {
+        !matches!(self, SortKey::Rank | SortKey::Name)
+    }
+}
+
+// 排行榜页与 /api/rank 共用的查询参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingQuery {
+    pub sort: SortKey,
+    pub desc: bool,
+    pub q: String,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl ListingQuery {
+    pub fn parse(query: &HashMap<String, String>) -> Result<Self, String> {
+        let get = |key: &str| query.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let sort = match get("sort") {
+            Some(s) => SortKey::parse(s).ok_or_else(|| {
+                format!(
+                    "invalid sort {}, expected one of {}",
+                    s,
+                    SORT_KEYS.map(|k| k.as_str()).join(", ")
+                )
+            })?,
+            None => SortKey::Rank,
+        };
+        let desc = match get("order") {
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(o) => return Err(format!("invalid order {}, expected asc or desc", o)),
+            None => sort.default_desc(),
+        };
+        let number = |key: &str, default: usize| match get(key) {
+            Some(v) => v
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)
+                .ok_or_else(|| format!("invalid {}, expected a positive integer", key)),
+            None => Ok(default),
+        };
+        Ok(ListingQuery {
+            sort,
+            desc,
+            q: get("q")
+                .unwrap_or_default()
+                .chars()
+                .take(MAX_SEARCH_CHARS)
+                .collect(),
+            page: number("page", 1)?,
+            per_page: number("per_page", DEFAULT_PER_PAGE)?.min(MAX_PER_PAGE),
+        })
+    }
+
+    // 参数按固定顺序拼接并省略默认值，同一结果只对应一个链接
+    pub fn query_string(&self) -> String {
+        let mut s = url::form_urlencoded::Serializer::new(String::new());
+        if self.sort != SortKey::Rank {
+            s.append_pair("sort", self.sort.as_str());
+        }
+        if self.desc != self.sort.default_desc() {
+            s.append_pair("order", if self.desc { "desc" } else { "asc" });
+        }
+        if !self.q.is_empty() {
+            s.append_pair("q", &self.q);
+        }
+        if self.per_page != DEFAULT_PER_PAGE {
+            s.append_pair("per_page", &self.per_page.to_string());
+        }
+        if self.page != 1 {
+            s.append_pair("page", &self.page.to_string());
+        }
+        let s = s.finish();
+        if s.is_empty() {
+            s
+        } else {
+            format!("?{}", s)
+        }
+    }
+
+    pub fn with_page(&self, page: usize) -> Self {
+        ListingQuery {
+            page,
+            ..self.clone()
+        }
+    }
+
+    // 点击当前排序键时切换顺序，换排序键时回到第一页
+    pub fn with_sort(&self, sort: SortKey) -> Self {
+        ListingQuery {
+            sort,
+            desc: if sort == self.sort {
+                !self.desc
+            } else {
+                sort.default_desc()
+            },
+            page: 1,
+            ..self.clone()
+        }
+    }
+}
+
+pub struct ListedMember {
+    // 在排行榜默认顺序中的名次
+    pub position: usize,
+    pub joined: NaiveDate,
+    pub last_active: NaiveDateTime,
+    pub level: i64,
+    pub entry: RankAndMembership,
+}
+
+pub struct Listing {
+    pub total: usize,
+    pub pages: usize,
+    pub members: Vec<ListedMember>,
+}
+
+/// `GET /api/rank` 中的一个成员
+#[derive(Serialize)]
+pub struct ListingItem {
+    pub position: usize,
+    pub domain: String,
+    pub name: String,
+    pub description: String,
+    pub unique_visitor: i64,
+    pub referrer: i64,
+    pub last_active: NaiveDateTime,
+    pub joined: NaiveDate,
+    pub level: i64,
+    pub movement: Option<RankMovement>,
+}
+
+impl From<&ListedMember> for ListingItem {
+    fn from(m: &ListedMember) -> Self {
+        ListingItem {
+            position: m.position,
+            domain: m.entry.membership.domain.clone(),
+            name: m.entry.membership.name.clone(),
+            description: m.entry.membership.description.clone(),
+            unique_visitor: m.entry.rank.unique_visitor,
+            referrer: m.entry.rank.referrer,
+            last_active: m.last_active,
+            joined: m.joined,
+            level: m.level,
+            movement: m.entry.movement,
+        }
+    }
+}
+
+// 30 天内活跃的成员按排行榜顺序编号后再搜索、排序、分页
+pub async fn list_members(ctx: &Context, query: &ListingQuery) -> Result<Listing, anyhow::Error> {
+    let now = ctx.clock.now();
+    let all = ctx
+        .rank_cache
+        .get(&ctx.db_pool, RankPeriod::AllTime, now)
+        .await?;
+    let days = RankPeriod::AllTime.days(now.date());
+    let ranked = ctx
+        .rank_list(
+            "all",
+            all.iter().cloned(),
+            &ctx.rank_context(now, days).await,
+        )
+        .await;
+    let movements = ctx.movements("all", &ranked).await;
+
+    let needle = query.q.to_lowercase();
+    let mut members = Vec::new();
+    for (i, r) in ranked.into_iter().enumerate() {
+        let membership = ctx.id2member.get(&r.membership_id).unwrap().to_owned();
+        let matched = needle.is_empty()
+            || [
+                &membership.domain,
+                &membership.name,
+                &membership.description,
+            ]
+            .iter()
+            .any(|v| v.to_lowercase().contains(&needle));
+        if !matched {
+            continue;
+        }
+        members.push(ListedMember {
+            position: i + 1,
+            joined: membership.joined_at.unwrap_or(r.created_at.date()),
+            last_active: last_active(&r),
+            level: ctx.level_of(&r.membership_id).await,
+            entry: RankAndMembership {
+                movement: movements.get(&r.membership_id).cloned(),
+                membership,
+                rank: r,
+            },
+        });
+    }
+
+    // 相同值按排行榜顺序
+    members.sort_by(|a, b| {
+        let order = match query.sort {
+            SortKey::Rank => a.position.cmp(&b.position),
+            SortKey::Referrer => a.entry.rank.referrer.cmp(&b.entry.rank.referrer),
+            SortKey::Visitor => a
+                .entry
+                .rank
+                .unique_visitor
+                .cmp(&b.entry.rank.unique_visitor),
+            SortKey::LastActive => a.last_active.cmp(&b.last_active),
+            SortKey::Joined => a.joined.cmp(&b.joined),
+            SortKey::Name => a
+                .entry
+                .membership
+                .name
+                .to_lowercase()
+                .cmp(&b.entry.membership.name.to_lowercase()),
+        };
+        if query.desc { order.reverse() } else { order }.then(a.position.cmp(&b.position))
+    });
+
+    let total = members.len();
+    let pages = total.div_ceil(query.per_page).max(1);
+    let members = members
+        .into_iter()
+        // 页码超出时得到空页，很大的页码也不会溢出
+        .skip(query.page.saturating_sub(1).saturating_mul(query.per_page))
+        .take(query.per_page)
+        .collect();
+    Ok(Listing {
+        total,
+        pages,
+        members,
+    })
+}
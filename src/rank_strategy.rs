@@ -0,0 +1,524 @@
+use std::{cmp::Ordering, collections::HashMap, env, sync::Arc};
+
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+
+use crate::{quality_model::QualityMap, statistics_model::Statistics};
+
+// 排序时可用的外部信息
+pub struct RankContext {
+    pub now: NaiveDateTime,
+    // 榜单区间内各成员的访问质量
+    pub quality: Arc<QualityMap>,
+    // 随机排序的种子，同一种子得到同样的顺序
+    pub seed: u64,
+}
+
+// 排行策略：对同一份统计数据原地排序，列表截断由调用方负责
+pub trait RankStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn rank(&self, entries: &mut [Statistics], ctx: &RankContext);
+}
+
+// 最后一次访问或带来访问的时间
+pub fn last_active(s: &Statistics) -> NaiveDateTime {
+    s.updated_at.max(
+        s.latest_referrer_at
+            .unwrap_or(NaiveDateTime::from_timestamp(0, 0)),
+    )
+}
+
+fn by_score_desc(entries: &mut [Statistics], score: impl Fn(&Statistics) -> f64) {
+    entries.sort_by(|a, b| {
+        score(b)
+            .partial_cmp(&score(a))
+            .unwrap_or(Ordering::Equal)
+            .then(b.referrer.cmp(&a.referrer))
+            .then(a.membership_id.cmp(&b.membership_id))
+    });
+}
+
+// 今日排名：最近一次带来访问的时间，其次访客数
+pub struct LatestReferrer;
+
+impl RankStrategy for LatestReferrer {
+    fn name(&self) -> &'static str {
+        "latest_referrer"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], _ctx: &RankContext) {
+        entries.sort_by(|a, b| {
+            b.latest_referrer_at
+                .cmp(&a.latest_referrer_at)
+                .then(b.unique_visitor.cmp(&a.unique_visitor))
+        });
+    }
+}
+
+// 区间排名：SUM(referrer)，其次 SUM(unique_visitor)
+pub struct ReferrerThenVisitor;
+
+impl RankStrategy for ReferrerThenVisitor {
+    fn name(&self) -> &'static str {
+        "referrer_then_visitor"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], _ctx: &RankContext) {
+        entries.sort_by(|a, b| {
+            b.referrer
+                .cmp(&a.referrer)
+                .then(b.unique_visitor.cmp(&a.unique_visitor))
+        });
+    }
+}
+
+// SplitMix64，结果不依赖 rand 的版本，分享出去的种子以后也能复现
+fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// 按活跃程度加权的随机顺序：权重 w = max(min_weight, 0.5^(距最后活跃天数 / half_life_days))，
+// 每个成员取 u ∈ (0, 1) 由种子与成员 id 决定，按 ln(u) / w 从大到小排列（加权无放回抽样）
+pub struct Random {
+    pub half_life_days: f64,
+    pub min_weight: f64,
+}
+
+impl Random {
+    pub fn weight(&self, s: &Statistics, now: NaiveDateTime) -> f64 {
+        let age_days = (now - last_active(s)).num_minutes().max(0) as f64 / 1440.0;
+        0.5f64
+            .powf(age_days / self.half_life_days)
+            .max(self.min_weight)
+    }
+
+    fn key(&self, s: &Statistics, ctx: &RankContext) -> f64 {
+        let bits = mix(ctx.seed ^ mix(s.membership_id as u64)) >> 11;
+        let u = (bits as f64 + 0.5) / (1u64 << 53) as f64;
+        u.ln() / self.weight(s, ctx.now)
+    }
+}
+
+impl RankStrategy for Random {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], ctx: &RankContext) {
+        entries.sort_by(|a, b| {
+            self.key(b, ctx)
+                .partial_cmp(&self.key(a, ctx))
+                .unwrap_or(Ordering::Equal)
+                .then(a.membership_id.cmp(&b.membership_id))
+        });
+    }
+}
+
+// 加权得分：referrer * referrer_weight + unique_visitor * visitor_weight
+pub struct WeightedScore {
+    pub referrer_weight: f64,
+    pub visitor_weight: f64,
+}
+
+impl WeightedScore {
+    pub fn score(&self, s: &Statistics) -> f64 {
+        s.referrer as f64 * self.referrer_weight + s.unique_visitor as f64 * self.visitor_weight
+    }
+}
+
+impl RankStrategy for WeightedScore {
+    fn name(&self) -> &'static str {
+        "weighted"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], _ctx: &RankContext) {
+        by_score_desc(entries, |s| self.score(s));
+    }
+}
+
+// 时间衰减得分：加权得分按最后活跃时间距今的时长以 half_life_hours 为半衰期衰减
+pub struct TimeDecayed {
+    pub weighted: WeightedScore,
+    pub half_life_hours: f64,
+}
+
+impl RankStrategy for TimeDecayed {
+    fn name(&self) -> &'static str {
+        "decayed"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], ctx: &RankContext) {
+        by_score_desc(entries, |s| {
+            let age_hours = ((ctx.now - last_active(s)).num_minutes().max(0)) as f64 / 60.0;
+            self.weighted.score(s) * 0.5f64.powf(age_hours / self.half_life_hours)
+        });
+    }
+}
+
+// 只看 referrer，相同时最近带来访问者靠前
+pub struct ReferrerOnly;
+
+impl RankStrategy for ReferrerOnly {
+    fn name(&self) -> &'static str {
+        "referrer_only"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], _ctx: &RankContext) {
+        entries.sort_by(|a, b| {
+            b.referrer
+                .cmp(&a.referrer)
+                .then(b.latest_referrer_at.cmp(&a.latest_referrer_at))
+        });
+    }
+}
+
+// 按访问来源多样性加权：同一网段的访问有上限，UA、国家越分散保留越多，见 quality_model
+pub struct DiversityWeighted {
+    pub weighted: WeightedScore,
+}
+
+impl RankStrategy for DiversityWeighted {
+    fn name(&self) -> &'static str {
+        "diversity"
+    }
+
+    fn rank(&self, entries: &mut [Statistics], ctx: &RankContext) {
+        by_score_desc(entries, |s| match ctx.quality.get(&s.membership_id) {
+            Some(q) => {
+                q.weighted_referrer(s.referrer) * self.weighted.referrer_weight
+                    + q.weighted_visitor(s.unique_visitor) * self.weighted.visitor_weight
+            }
+            None => self.weighted.score(s),
+        });
+    }
+}
+
+fn parse_params(params: Option<&str>, defaults: &[f64]) -> Result<Vec<f64>, anyhow::Error> {
+    let mut values = defaults.to_vec();
+    if let Some(params) = params {
+        for (i, p) in params.split(',').enumerate() {
+            if i >= values.len() {
+                return Err(anyhow!("too many parameters: {}", params));
+            }
+            values[i] = p
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| anyhow!("invalid parameter: {}", p))?;
+        }
+    }
+    Ok(values)
+}
+
+// 解析策略配置，格式为 名称[:参数,参数]，例如 weighted:2,1、decayed:24
+pub fn strategy_from_config(config: &str) -> Result<Arc<dyn RankStrategy>, anyhow::Error> {
+    let (name, params) = match config.split_once(':') {
+        Some((name, params)) => (name.trim(), Some(params)),
+        None => (config.trim(), None),
+    };
+    Ok(match name {
+        "latest_referrer" => Arc::new(LatestReferrer),
+        "referrer_then_visitor" => Arc::new(ReferrerThenVisitor),
+        "random" => {
+            let p = parse_params(params, &[7.0, 0.05])?;
+            if p[0] <= 0.0 || p[1] <= 0.0 || p[1] > 1.0 {
+                return Err(anyhow!(
+                    "half life must be positive and min weight in (0, 1]"
+                ));
+            }
+            Arc::new(Random {
+                half_life_days: p[0],
+                min_weight: p[1],
+            })
+        }
+        "referrer_only" => Arc::new(ReferrerOnly),
+        "weighted" => {
+            let p = parse_params(params, &[2.0, 1.0])?;
+            Arc::new(WeightedScore {
+                referrer_weight: p[0],
+                visitor_weight: p[1],
+            })
+        }
+        "diversity" => {
+            let p = parse_params(params, &[2.0, 1.0])?;
+            Arc::new(DiversityWeighted {
+                weighted: WeightedScore {
+                    referrer_weight: p[0],
+                    visitor_weight: p[1],
+                },
+            })
+        }
+        "decayed" => {
+            let p = parse_params(params, &[24.0, 2.0, 1.0])?;
+            if p[0] <= 0.0 {
+                return Err(anyhow!("half life must be positive"));
+            }
+            Arc::new(TimeDecayed {
+                half_life_hours: p[0],
+                weighted: WeightedScore {
+                    referrer_weight: p[1],
+                    visitor_weight: p[2],
+                },
+            })
+        }
+        _ => return Err(anyhow!("unknown rank strategy {}", name)),
+    })
+}
+
+// 随机种子缺省的轮换间隔（分钟）
+const RANDOM_SEED_MINUTES: i64 = 60;
+
+// 各榜单使用的策略，可通过环境变量 RANK_STRATEGY_<榜单> 覆盖
+pub struct RankStrategies {
+    strategies: HashMap<&'static str, Arc<dyn RankStrategy>>,
+    // 随机种子的轮换间隔，环境变量 RANDOM_SEED_MINUTES
+    seed_minutes: i64,
+}
+
+impl RankStrategies {
+    const DEFAULTS: [(&'static str, &'static str); 8] = [
+        ("daily", "diversity"),
+        ("weekly", "diversity"),
+        ("monthly", "diversity"),
+        ("calendar_month", "diversity"),
+        ("yearly", "diversity"),
+        ("custom", "diversity"),
+        ("random", "random"),
+        ("all", "diversity"),
+    ];
+
+    pub fn from_env() -> Self {
+        let mut strategies = HashMap::new();
+        for (list, default) in Self::DEFAULTS {
+            let key = format!("RANK_STRATEGY_{}", list.to_uppercase());
+            let strategy = env::var(&key)
+                .ok()
+                .and_then(|config| match strategy_from_config(&config) {
+                    Ok(s) => Some(s),
+                    Err(e) => {
+                        tracing::warn!("{}: {}, fallback to {}", key, e, default);
+                        None
+                    }
+                })
+                .unwrap_or_else(|| strategy_from_config(default).unwrap());
+            tracing::info!("rank strategy {} = {}", list, strategy.name());
+            strategies.insert(list, strategy);
+        }
+        let seed_minutes = env::var("RANDOM_SEED_MINUTES")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(RANDOM_SEED_MINUTES);
+        RankStrategies {
+            strategies,
+            seed_minutes,
+        }
+    }
+
+    // 同一轮换周期内种子不变，页面可以缓存
+    pub fn rotating_seed(&self, now: NaiveDateTime) -> u64 {
+        (now.timestamp() / 60 / self.seed_minutes) as u64
+    }
+
+    // 种子所在轮换周期的起点；随机顺序按这个时间计算权重，同一种子以后也能复现
+    pub fn seed_time(&self, seed: u64) -> Option<NaiveDateTime> {
+        i64::try_from(seed)
+            .ok()
+            .and_then(|s| s.checked_mul(self.seed_minutes * 60))
+            .and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, 0))
+    }
+
+    pub fn for_list(&self, list: &str) -> Arc<dyn RankStrategy> {
+        self.strategies
+            .get(list)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(ReferrerThenVisitor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quality_model::{QualityScore, VisitQuality};
+    use chrono::NaiveDate;
+    use std::collections::HashSet;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, 18)
+            .and_then(|d| d.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    fn stat(
+        member: i64,
+        referrer: i64,
+        unique_visitor: i64,
+        updated_at: NaiveDateTime,
+        latest_referrer_at: Option<NaiveDateTime>,
+    ) -> Statistics {
+        Statistics {
+            id: member as i32,
+            created_at: at(0, 0),
+            updated_at,
+            membership_id: member,
+            unique_visitor,
+            referrer,
+            latest_referrer_at,
+        }
+    }
+
+    // 1 与 6 除 id 外完全相同，用来检查并列时的顺序
+    fn fixture() -> Vec<Statistics> {
+        vec![
+            stat(1, 5, 10, at(11, 0), Some(at(11, 30))),
+            stat(2, 5, 20, at(8, 0), Some(at(9, 0))),
+            stat(3, 8, 2, at(2, 0), Some(at(2, 0))),
+            stat(4, 0, 30, at(11, 50), None),
+            stat(5, 5, 12, at(10, 0), Some(at(11, 30))),
+            stat(6, 5, 10, at(11, 0), Some(at(11, 30))),
+        ]
+    }
+
+    fn context(quality: QualityMap, seed: u64) -> RankContext {
+        RankContext {
+            now: at(12, 0),
+            quality: Arc::new(quality),
+            seed,
+        }
+    }
+
+    fn ranked(
+        strategy: &dyn RankStrategy,
+        mut entries: Vec<Statistics>,
+        ctx: &RankContext,
+    ) -> Vec<i64> {
+        strategy.rank(&mut entries, ctx);
+        entries.iter().map(|s| s.membership_id).collect()
+    }
+
+    fn weighted() -> WeightedScore {
+        WeightedScore {
+            referrer_weight: 2.0,
+            visitor_weight: 1.0,
+        }
+    }
+
+    // 相同的最后 referrer 时间按访客数，仍相同时保持原有顺序
+    #[test]
+    fn latest_referrer_orders_by_time_then_visitors() {
+        let ctx = context(QualityMap::new(), 0);
+        assert_eq!(ranked(&LatestReferrer, fixture(), &ctx), [5, 1, 6, 2, 3, 4]);
+    }
+
+    #[test]
+    fn referrer_then_visitor_orders_by_sums() {
+        let ctx = context(QualityMap::new(), 0);
+        assert_eq!(
+            ranked(&ReferrerThenVisitor, fixture(), &ctx),
+            [3, 2, 5, 1, 6, 4]
+        );
+    }
+
+    #[test]
+    fn referrer_only_breaks_ties_by_latest_referrer() {
+        let ctx = context(QualityMap::new(), 0);
+        assert_eq!(ranked(&ReferrerOnly, fixture(), &ctx), [3, 1, 5, 6, 2, 4]);
+    }
+
+    // 得分相同时 referrer 多者在前，再按 id，与输入顺序无关
+    #[test]
+    fn weighted_breaks_ties_by_referrer_then_id() {
+        let ctx = context(QualityMap::new(), 0);
+        let mut reversed = fixture();
+        reversed.reverse();
+        assert_eq!(ranked(&weighted(), fixture(), &ctx), [2, 4, 5, 1, 6, 3]);
+        assert_eq!(ranked(&weighted(), reversed, &ctx), [2, 4, 5, 1, 6, 3]);
+    }
+
+    // 4 与 2 得分相同，4 更近活跃，衰减后排在前面
+    #[test]
+    fn decayed_prefers_recent_activity() {
+        let ctx = context(QualityMap::new(), 0);
+        let decayed = TimeDecayed {
+            weighted: weighted(),
+            half_life_hours: 24.0,
+        };
+        assert_eq!(ranked(&decayed, fixture(), &ctx), [4, 2, 5, 1, 6, 3]);
+    }
+
+    // 2 的访问集中在同一网段，加权后降到最后；没有来源记录的成员按原值计分
+    #[test]
+    fn diversity_discounts_concentrated_visits() {
+        let mut quality = QualityMap::new();
+        quality.insert(
+            2,
+            QualityScore {
+                visitor: VisitQuality {
+                    visits: 20,
+                    weighted: 4.0,
+                    ..Default::default()
+                },
+                referrer: VisitQuality {
+                    visits: 5,
+                    weighted: 1.0,
+                    ..Default::default()
+                },
+                flags: vec![],
+            },
+        );
+        let ctx = context(quality, 0);
+        let diversity = DiversityWeighted {
+            weighted: weighted(),
+        };
+        assert_eq!(ranked(&diversity, fixture(), &ctx), [4, 5, 1, 6, 3, 2]);
+    }
+
+    #[test]
+    fn random_is_reproducible_from_seed() {
+        let random = Random {
+            half_life_days: 7.0,
+            min_weight: 0.05,
+        };
+        let ctx = context(QualityMap::new(), 42);
+        let mut reversed = fixture();
+        reversed.reverse();
+        let order = ranked(&random, fixture(), &ctx);
+        // 种子对应的顺序固定，分享出去的链接以后仍能复现
+        assert_eq!(order, [5, 4, 1, 3, 6, 2]);
+        assert_eq!(ranked(&random, reversed, &ctx), order);
+
+        let orders: HashSet<Vec<i64>> = (0..20)
+            .map(|seed| ranked(&random, fixture(), &context(QualityMap::new(), seed)))
+            .collect();
+        assert!(orders.len() > 1);
+    }
+
+    #[test]
+    fn seed_time_is_start_of_rotation() {
+        let strategies = RankStrategies {
+            strategies: HashMap::new(),
+            seed_minutes: 60,
+        };
+        let seed = strategies.rotating_seed(at(12, 34));
+        assert_eq!(strategies.seed_time(seed), Some(at(12, 0)));
+        assert_eq!(strategies.rotating_seed(at(12, 0)), seed);
+        assert_eq!(strategies.seed_time(u64::MAX), None);
+    }
+
+    // 权重每过一个半衰期减半，不低于下限
+    #[test]
+    fn random_weight_decays_to_minimum() {
+        let random = Random {
+            half_life_days: 7.0,
+            min_weight: 0.05,
+        };
+        let now = at(12, 0);
+        let week_ago = stat(1, 0, 0, now - chrono::Duration::days(7), None);
+        let year_ago = stat(2, 0, 0, now - chrono::Duration::days(365), None);
+        assert_eq!(random.weight(&stat(3, 0, 0, now, None), now), 1.0);
+        assert!((random.weight(&week_ago, now) - 0.5).abs() < 1e-9);
+        assert_eq!(random.weight(&year_ago, now), 0.05);
+    }
+}
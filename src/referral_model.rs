@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use crate::schema::referral_edges::{self, dsl::*};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::upsert::excluded;
+use diesel::SqliteConnection;
+
+// 一条成员之间的引流：source 的站点把访客带给 target。
+// visitor_type 为 VisitorType：1 为从 source 来到联盟后点击了 target，其余为 source 的页面上嵌入了 target 的徽章、卡片或图标
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReferralKey {
+    pub source_id: i64,
+    pub target_id: i64,
+    pub visitor_type: i32,
+}
+
+// 引流方式，与 VisitorType 对应
+pub fn referral_kind(vt: i32) -> &'static str {
+    match vt {
+        1 => "click",
+        2 => "badge",
+        3 => "icon",
+        4 => "favicon",
+        5 => "card",
+        _ => "unknown",
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReferralEdge {
+    pub source_id: i64,
+    pub target_id: i64,
+    pub visitor_type: i32,
+    pub count: i64,
+}
+
+impl ReferralEdge {
+    // 把当天的引流次数累加到 (日期, 来源, 目标, 访问类型) 这条边上
+    pub fn increment_all(
+        conn: &mut SqliteConnection,
+        day: NaiveDateTime,
+        rows: &HashMap<ReferralKey, i64>,
+    ) -> Result<(), diesel::result::Error> {
+        conn.transaction(|conn| {
+            for (key, delta) in rows {
+                diesel::insert_into(referral_edges::table)
+                    .values((
+                        created_at.eq(day),
+                        source_id.eq(key.source_id),
+                        target_id.eq(key.target_id),
+                        visitor_type.eq(key.visitor_type),
+                        count.eq(delta),
+                    ))
+                    .on_conflict((created_at, source_id, target_id, visitor_type))
+                    .do_update()
+                    .set(count.eq(count + excluded(count)))
+                    .execute(conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    // 日期区间内的引流合计，可只看与某个成员有关的；按次数从多到少
+    pub fn between(
+        conn: &mut SqliteConnection,
+        member: Option<i64>,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<ReferralEdge>, anyhow::Error> {
+        let mut query = referral_edges
+            .select((
+                source_id,
+                target_id,
+                visitor_type,
+                sql::<diesel::sql_types::BigInt>("SUM(count) AS s_count"),
+            ))
+            .filter(created_at.between(start, end))
+            .group_by((source_id, target_id, visitor_type))
+            .order_by(sql::<diesel::sql_types::BigInt>("s_count DESC"))
+            .then_order_by((source_id, target_id, visitor_type))
+            .into_boxed();
+        if let Some(member) = member {
+            query = query.filter(source_id.eq(member).or(target_id.eq(member)));
+        }
+        let rows = query
+            .load::<(i64, i64, i32, i64)>(conn)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        Ok(rows
+            .into_iter()
+            .map(|(s, t, vt, c)| ReferralEdge {
+                source_id: s,
+                target_id: t,
+                visitor_type: vt,
+                count: c,
+            })
+            .collect())
+    }
+}
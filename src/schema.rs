@@ -1,5 +1,16 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    referral_edges (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        source_id -> BigInt,
+        target_id -> BigInt,
+        visitor_type -> Integer,
+        count -> BigInt,
+    }
+}
+
 diesel::table! {
     statistics (id) {
         id -> Integer,
@@ -11,3 +22,45 @@ diesel::table! {
         latest_referrer_at -> Nullable<Timestamp>,
     }
 }
+
+diesel::table! {
+    lifecycle_transitions (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        membership_id -> BigInt,
+        from_state -> Text,
+        to_state -> Text,
+        idle_days -> Integer,
+        last_active_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    rank_history (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        list -> Text,
+        membership_id -> BigInt,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    visit_diversity (id) {
+        id -> Integer,
+        created_at -> Timestamp,
+        membership_id -> BigInt,
+        visitor_type -> Integer,
+        dimension -> Text,
+        value -> Text,
+        count -> BigInt,
+    }
+}
+
+diesel::allow_tables_to_appear_in_same_query!(
+    lifecycle_transitions,
+    rank_history,
+    referral_edges,
+    statistics,
+    visit_diversity,
+);
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{Duration, NaiveDate};
+
+use crate::{clock::start_of_day, statistics_model::Statistics, DbPool};
+
+// 趋势图在卡片上的位置：域名下方、底栏上方
+const SPARKLINE_X: f32 = 16.0;
+const SPARKLINE_Y: f32 = 114.0;
+const SPARKLINE_WIDTH: f32 = 288.0;
+const SPARKLINE_HEIGHT: f32 = 20.0;
+
+// 卡片趋势图的天数，?trend=7d|30d
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrendRange {
+    Week,
+    Month,
+}
+
+impl TrendRange {
+    pub fn parse(query: &HashMap<String, String>) -> Result<Self, String> {
+        match query.get("trend").map(|v| v.trim()).unwrap_or("") {
+            "" | "7" | "7d" => Ok(TrendRange::Week),
+            "30" | "30d" => Ok(TrendRange::Month),
+            other => Err(format!("invalid trend {}, expected 7d or 30d", other)),
+        }
+    }
+
+    pub fn days(&self) -> i64 {
+        match self {
+            TrendRange::Week => 7,
+            TrendRange::Month => 30,
+        }
+    }
+
+    // PNG 缓存中区分两种卡片
+    pub fn png_kind(&self) -> &'static str {
+        match self {
+            TrendRange::Week => "card",
+            TrendRange::Month => "card-30d",
+        }
+    }
+}
+
+// 每日 (独立访客, referrer)，按日期排列
+pub type DailyPoints = Vec<(i64, i64)>;
+
+// 截至今天的 days 天中今天以前的 days - 1 天，没有统计的日期为 0；日期按上海时间划分
+pub fn daily_points(
+    db_pool: &DbPool,
+    member: i64,
+    days: i64,
+    today: NaiveDate,
+) -> Result<DailyPoints, anyhow::Error> {
+    let start = today - Duration::days(days - 1);
+    let rows = Statistics::between(
+        db_pool.get()?,
+        Some(member),
+        start_of_day(start),
+        start_of_day(today) - Duration::seconds(1),
+    )?;
+    let by_day: HashMap<NaiveDate, (i64, i64)> = rows
+        .iter()
+        .map(|s| (s.created_at.date(), (s.unique_visitor, s.referrer)))
+        .collect();
+    Ok((0..days - 1)
+        .map(|i| {
+            by_day
+                .get(&(start + Duration::days(i)))
+                .cloned()
+                .unwrap_or((0, 0))
+        })
+        .collect())
+}
+
+type SparklineEntries = HashMap<(i64, TrendRange), (NaiveDate, Arc<DailyPoints>)>;
+
+// 各成员今天以前的每日计数，当天内不变，跨天后重新读取
+#[derive(Default)]
+pub struct SparklineCache {
+    entries: Mutex<SparklineEntries>,
+}
+
+impl SparklineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(
+        &self,
+        db_pool: &DbPool,
+        member: i64,
+        range: TrendRange,
+        today: NaiveDate,
+    ) -> Result<Arc<DailyPoints>, anyhow::Error> {
+        if let Some((day, points)) = self.entries.lock().unwrap().get(&(member, range)) {
+            if *day == today {
+                return Ok(points.clone());
+            }
+        }
+        let points = Arc::new(daily_points(db_pool, member, range.days(), today)?);
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((member, range), (today, points.clone()));
+        Ok(points)
+    }
+}
+
+// 卡片上的折线，两条线共用纵轴
+pub struct Sparkline {
+    pub unique_visitor: String,
+    pub unique_visitor_area: String,
+    pub referrer: String,
+}
+
+impl Sparkline {
+    pub fn build(points: &[(i64, i64)]) -> Self {
+        let max = points
+            .iter()
+            .map(|(uv, rv)| (*uv).max(*rv))
+            .max()
+            .unwrap_or(0)
+            .max(1) as f32;
+        let step = SPARKLINE_WIDTH / (points.len().max(2) - 1) as f32;
+        let bottom = SPARKLINE_Y + SPARKLINE_HEIGHT;
+        let line = |value: fn(&(i64, i64)) -> i64| {
+            points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    format!(
+                        "{}{:.1} {:.1}",
+                        if i == 0 { "M" } else { "L" },
+                        SPARKLINE_X + step * i as f32,
+                        bottom - SPARKLINE_HEIGHT * value(p) as f32 / max
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let unique_visitor = line(|p| p.0);
+        let last_x = SPARKLINE_X + step * (points.len().max(1) - 1) as f32;
+        Sparkline {
+            unique_visitor_area: format!(
+                "{} L{:.1} {:.1} L{:.1} {:.1} Z",
+                unique_visitor, last_x, bottom, SPARKLINE_X, bottom
+            ),
+            unique_visitor,
+            referrer: line(|p| p.1),
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::{NaiveDate, NaiveDateTime};
+use diesel::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::membership_model::Membership;
+use crate::statistics_model::{self, Statistics};
+use crate::DbPool;
+
+// 导出/导入的单行记录，附带成员域名与名称方便离线分析
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatisticsRecord {
+    pub membership_id: i64,
+    pub domain: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub unique_visitor: i64,
+    pub referrer: i64,
+    pub latest_referrer_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::JsonLines => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::JsonLines => "jsonl",
+        }
+    }
+
+    // 根据文件扩展名推断格式
+    pub fn from_path(path: &str) -> Option<ExportFormat> {
+        path.rsplit_once('.')
+            .and_then(|(_, ext)| ExportFormat::from_str(ext).ok())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "ndjson" | "json" => Ok(ExportFormat::JsonLines),
+            _ => Err(anyhow!("unknown format {}, expected csv or jsonl", s)),
+        }
+    }
+}
+
+// 导入时遇到相同 (membership_id, created_at) 的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    // 以导入数据覆盖；服务写库是累加增量，需要累加时用 Sum
+    Replace,
+    // 保留数据库中已有数据
+    Skip,
+    // 各计数取较大值
+    Max,
+    // 各计数相加
+    Sum,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(ConflictPolicy::Replace),
+            "skip" => Ok(ConflictPolicy::Skip),
+            "max" => Ok(ConflictPolicy::Max),
+            "sum" => Ok(ConflictPolicy::Sum),
+            _ => Err(anyhow!(
+                "unknown conflict policy {}, expected replace, skip, max or sum",
+                s
+            )),
+        }
+    }
+}
+
+pub struct ExportFilter {
+    pub membership_id: Option<i64>,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl ExportFilter {
+    // 日期均为闭区间，缺省时不限制
+    pub fn new(membership_id: Option<i64>, from: Option<NaiveDate>, to: Option<NaiveDate>) -> Self {
+        ExportFilter {
+            membership_id,
+            start: from
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+                .unwrap_or_else(|| NaiveDateTime::from_timestamp_opt(0, 0).unwrap()),
+            end: to
+                .unwrap_or_else(|| NaiveDate::from_ymd_opt(9999, 12, 31).unwrap())
+                .and_hms_opt(0, 0, 0)
+                .unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub unknown_member: usize,
+}
+
+// 按域名或 ID 查找成员
+pub fn find_member<'a>(members: &'a HashMap<i64, Membership>, key: &str) -> Option<&'a Membership> {
+    members
+        .values()
+        .find(|m| m.domain.eq(key))
+        .or_else(|| key.parse::<i64>().ok().and_then(|id| members.get(&id)))
+}
+
+pub fn export<W: Write>(
+    db_pool: &DbPool,
+    members: &HashMap<i64, Membership>,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    writer: W,
+) -> Result<usize, anyhow::Error> {
+    let rows = Statistics::between(
+        db_pool.get()?,
+        filter.membership_id,
+        filter.start,
+        filter.end,
+    )?;
+    let records = rows.into_iter().map(|s| {
+        let member = members.get(&s.membership_id);
+        StatisticsRecord {
+            membership_id: s.membership_id,
+            domain: member.map(|m| m.domain.clone()).unwrap_or_default(),
+            name: member.map(|m| m.name.clone()).unwrap_or_default(),
+            created_at: s.created_at,
+            updated_at: s.updated_at,
+            unique_visitor: s.unique_visitor,
+            referrer: s.referrer,
+            latest_referrer_at: s.latest_referrer_at,
+        }
+    });
+
+    let mut count = 0;
+    match format {
+        ExportFormat::Csv => {
+            let mut w = csv::Writer::from_writer(writer);
+            for r in records {
+                w.serialize(r)?;
+                count += 1;
+            }
+            w.flush()?;
+        }
+        ExportFormat::JsonLines => {
+            let mut w = writer;
+            for r in records {
+                serde_json::to_writer(&mut w, &r)?;
+                w.write_all(b"\n")?;
+                count += 1;
+            }
+            w.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+fn read_records<R: Read>(
+    format: ExportFormat,
+    reader: R,
+) -> Result<Vec<StatisticsRecord>, anyhow::Error> {
+    match format {
+        ExportFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .map(|r| r.map_err(|e| anyhow!("{}", e)))
+            .collect(),
+        ExportFormat::JsonLines => BufReader::new(reader)
+            .lines()
+            .filter(|l| l.as_ref().map(|l| !l.trim().is_empty()).unwrap_or(true))
+            .map(|l| Ok(serde_json::from_str(&l?)?))
+            .collect(),
+    }
+}
+
+fn merge(existing: &Statistics, record: &StatisticsRecord, policy: ConflictPolicy) -> Statistics {
+    let mut merged = existing.clone();
+    match policy {
+        ConflictPolicy::Replace => {
+            merged.unique_visitor = record.unique_visitor;
+            merged.referrer = record.referrer;
+            merged.updated_at = record.updated_at;
+            merged.latest_referrer_at = record.latest_referrer_at;
+        }
+        ConflictPolicy::Max => {
+            merged.unique_visitor = existing.unique_visitor.max(record.unique_visitor);
+            merged.referrer = existing.referrer.max(record.referrer);
+        }
+        ConflictPolicy::Sum => {
+            merged.unique_visitor = existing.unique_visitor + record.unique_visitor;
+            merged.referrer = existing.referrer + record.referrer;
+        }
+        ConflictPolicy::Skip => {}
+    }
+    if policy != ConflictPolicy::Replace {
+        merged.updated_at = existing.updated_at.max(record.updated_at);
+        merged.latest_referrer_at = existing.latest_referrer_at.max(record.latest_referrer_at);
+    }
+    merged
+}
+
+// 合并导入，成员优先按域名匹配，其次按 ID；整个文件在一个事务中写入
+pub fn import<R: Read>(
+    db_pool: &DbPool,
+    members: &HashMap<i64, Membership>,
+    format: ExportFormat,
+    reader: R,
+    policy: ConflictPolicy,
+) -> Result<ImportSummary, anyhow::Error> {
+    let records = read_records(format, reader)?;
+    let mut conn = db_pool.get()?;
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        let mut summary = ImportSummary::default();
+        for record in records.iter() {
+            let member =
+                find_member(members, &record.domain).or_else(|| members.get(&record.membership_id));
+            let member = match member {
+                Some(m) => m,
+                None => {
+                    summary.unknown_member += 1;
+                    continue;
+                }
+            };
+            let created_at = record.created_at.date().and_hms_opt(0, 0, 0).unwrap();
+            match Statistics::find(conn, member.id, created_at)? {
+                Some(_) if policy == ConflictPolicy::Skip => summary.skipped += 1,
+                Some(existing) => {
+                    statistics_model::upsert(conn, &merge(&existing, record, policy))?;
+                    summary.updated += 1;
+                }
+                None => {
+                    statistics_model::upsert(
+                        conn,
+                        &Statistics {
+                            id: 0,
+                            created_at,
+                            updated_at: record.updated_at,
+                            membership_id: member.id,
+                            unique_visitor: record.unique_visitor,
+                            referrer: record.referrer,
+                            latest_referrer_at: record.latest_referrer_at,
+                        },
+                    )?;
+                    summary.inserted += 1;
+                }
+            }
+        }
+        Ok(summary)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::membership_model::MEMBERSHIP_PATH;
+    use crate::test_db::TestDb;
+
+    fn day(d: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, d)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    }
+
+    fn row(member: i64, d: u32, uv: i64, rv: i64) -> Statistics {
+        Statistics {
+            id: 0,
+            created_at: day(d),
+            updated_at: day(d) + chrono::Duration::hours(12),
+            membership_id: member,
+            unique_visitor: uv,
+            referrer: rv,
+            latest_referrer_at: (rv > 0).then(|| day(d) + chrono::Duration::hours(10)),
+        }
+    }
+
+    fn stored(db: &TestDb, member: i64, d: u32) -> Option<(i64, i64)> {
+        Statistics::find(&mut db.pool.get().unwrap(), member, day(d))
+            .unwrap()
+            .map(|s| (s.unique_visitor, s.referrer))
+    }
+
+    // 两个成员的导出数据，末尾附加一行不存在的成员
+    fn exported(members: &HashMap<i64, Membership>, format: ExportFormat) -> (i64, i64, Vec<u8>) {
+        let mut ids: Vec<i64> = members.keys().copied().collect();
+        ids.sort();
+        let (a, b) = (ids[0], ids[1]);
+
+        let source = TestDb::new();
+        Statistics::increment_all(
+            &mut source.pool.get().unwrap(),
+            &[row(a, 1, 5, 2), row(b, 1, 3, 1), row(a, 2, 4, 0)],
+        )
+        .unwrap();
+        let mut out = Vec::new();
+        let filter = ExportFilter::new(None, None, None);
+        assert_eq!(
+            export(&source.pool, members, &filter, format, &mut out).unwrap(),
+            3
+        );
+
+        let unknown = StatisticsRecord {
+            membership_id: -1,
+            domain: "unknown.example".to_string(),
+            name: String::new(),
+            created_at: day(1),
+            updated_at: day(1),
+            unique_visitor: 9,
+            referrer: 9,
+            latest_referrer_at: None,
+        };
+        match format {
+            ExportFormat::Csv => {
+                let mut w = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut out);
+                w.serialize(&unknown).unwrap();
+                w.flush().unwrap();
+            }
+            ExportFormat::JsonLines => {
+                serde_json::to_writer(&mut out, &unknown).unwrap();
+                out.push(b'\n');
+            }
+        }
+        (a, b, out)
+    }
+
+    #[test]
+    fn export_then_import_round_trips() {
+        let members = Membership::load_all(MEMBERSHIP_PATH).unwrap();
+        for format in [ExportFormat::Csv, ExportFormat::JsonLines] {
+            let (a, b, data) = exported(&members, format);
+            let target = TestDb::new();
+            let summary = import(
+                &target.pool,
+                &members,
+                format,
+                data.as_slice(),
+                ConflictPolicy::Replace,
+            )
+            .unwrap();
+            assert_eq!(
+                (summary.inserted, summary.updated, summary.unknown_member),
+                (3, 0, 1)
+            );
+            assert_eq!(stored(&target, a, 1), Some((5, 2)));
+            assert_eq!(stored(&target, b, 1), Some((3, 1)));
+            assert_eq!(stored(&target, a, 2), Some((4, 0)));
+
+            let imported = Statistics::find(&mut target.pool.get().unwrap(), a, day(1))
+                .unwrap()
+                .unwrap();
+            assert_eq!(imported.updated_at, day(1) + chrono::Duration::hours(12));
+            assert_eq!(
+                imported.latest_referrer_at,
+                Some(day(1) + chrono::Duration::hours(10))
+            );
+        }
+    }
+
+    #[test]
+    fn import_merges_existing_rows_by_policy() {
+        let members = Membership::load_all(MEMBERSHIP_PATH).unwrap();
+        let (a, b, data) = exported(&members, ExportFormat::JsonLines);
+        for (policy, expected, skipped) in [
+            (ConflictPolicy::Replace, (5, 2), 0),
+            (ConflictPolicy::Skip, (7, 1), 1),
+            (ConflictPolicy::Max, (7, 2), 0),
+            (ConflictPolicy::Sum, (12, 3), 0),
+        ] {
+            let target = TestDb::new();
+            Statistics::increment_all(&mut target.pool.get().unwrap(), &[row(a, 1, 7, 1)]).unwrap();
+            let summary = import(
+                &target.pool,
+                &members,
+                ExportFormat::JsonLines,
+                data.as_slice(),
+                policy,
+            )
+            .unwrap();
+            assert_eq!(stored(&target, a, 1), Some(expected), "{:?}", policy);
+            assert_eq!(stored(&target, b, 1), Some((3, 1)), "{:?}", policy);
+            assert_eq!(
+                (
+                    summary.inserted,
+                    summary.updated,
+                    summary.skipped,
+                    summary.unknown_member
+                ),
+                (2, 1 - skipped, skipped, 1),
+                "{:?}",
+                policy
+            );
+        }
+    }
+}
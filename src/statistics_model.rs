@@ -1,12 +1,12 @@
-use std::ops::Sub;
+use std::collections::HashMap;
 
-use crate::now_shanghai;
 use crate::schema::statistics::{self, dsl::*};
 use anyhow::anyhow;
-use chrono::{Duration, NaiveDateTime, NaiveTime};
+use chrono::NaiveDateTime;
 use diesel::dsl::sql;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::sqlite::Sqlite;
+use diesel::upsert::excluded;
 use diesel::{debug_query, prelude::*};
 use diesel::{Queryable, SqliteConnection};
 use tracing::debug;
@@ -24,64 +24,78 @@ pub struct Statistics {
 }
 
 impl Statistics {
-    pub fn insert_or_update(
-        mut conn: PooledConnection<ConnectionManager<SqliteConnection>>,
-        stat: &Statistics,
-    ) -> Result<usize, diesel::result::Error> {
-        let statement = diesel::insert_into(statistics)
-            .values((
-                created_at.eq(stat.created_at),
-                updated_at.eq(stat.updated_at),
-                membership_id.eq(stat.membership_id),
-                unique_visitor.eq(stat.unique_visitor),
-                referrer.eq(stat.referrer),
-                latest_referrer_at.eq(stat.latest_referrer_at),
-            ))
-            .on_conflict((membership_id, created_at))
-            .do_update()
-            .set((
-                unique_visitor.eq(stat.unique_visitor),
-                referrer.eq(stat.referrer),
-                updated_at.eq(stat.updated_at),
-                latest_referrer_at.eq(stat.latest_referrer_at),
-            ));
-        debug!("sql: {}", debug_query::<Sqlite, _>(&statement));
-        statement.execute(&mut conn)
+    // 将各行计数累加到已有数据上，时间取较晚者；多个实例共用数据库时使用
+    pub fn increment_all(
+        conn: &mut SqliteConnection,
+        rows: &[Statistics],
+    ) -> Result<(), diesel::result::Error> {
+        conn.transaction(|conn| {
+            for stat in rows {
+                let statement = diesel::insert_into(statistics)
+                    .values((
+                        created_at.eq(stat.created_at),
+                        updated_at.eq(stat.updated_at),
+                        membership_id.eq(stat.membership_id),
+                        unique_visitor.eq(stat.unique_visitor),
+                        referrer.eq(stat.referrer),
+                        latest_referrer_at.eq(stat.latest_referrer_at),
+                    ))
+                    .on_conflict((membership_id, created_at))
+                    .do_update()
+                    .set((
+                        unique_visitor.eq(unique_visitor + excluded(unique_visitor)),
+                        referrer.eq(referrer + excluded(referrer)),
+                        updated_at.eq(sql::<diesel::sql_types::Timestamp>(
+                            "MAX(updated_at, excluded.updated_at)",
+                        )),
+                        latest_referrer_at.eq(sql::<
+                            diesel::sql_types::Nullable<diesel::sql_types::Timestamp>,
+                        >(
+                            "MAX(COALESCE(latest_referrer_at, excluded.latest_referrer_at), \
+                             COALESCE(excluded.latest_referrer_at, latest_referrer_at))",
+                        )),
+                    ));
+                debug!("sql: {}", debug_query::<Sqlite, _>(&statement));
+                statement.execute(conn)?;
+            }
+            Ok(())
+        })
     }
 
-    pub fn today(
-        conn: PooledConnection<ConnectionManager<SqliteConnection>>,
-    ) -> Result<Vec<Statistics>, anyhow::Error> {
-        load_statistics_by_created_at(
-            conn,
-            NaiveDateTime::new(now_shanghai().date(), NaiveTime::from_hms(0, 0, 0)),
-        )
+    pub fn find(
+        conn: &mut SqliteConnection,
+        _membership_id: i64,
+        _created_at: NaiveDateTime,
+    ) -> Result<Option<Statistics>, diesel::result::Error> {
+        statistics
+            .filter(
+                membership_id
+                    .eq(_membership_id)
+                    .and(created_at.eq(_created_at)),
+            )
+            .first::<Statistics>(conn)
+            .optional()
     }
 
-    pub fn prev_day_rank_avg(conn: PooledConnection<ConnectionManager<SqliteConnection>>) -> i64 {
-        let res = load_statistics_by_created_at(
-            conn,
-            NaiveDateTime::new(now_shanghai().date(), NaiveTime::from_hms(0, 0, 0))
-                .sub(Duration::hours(24)),
-        );
-        if let Ok(res) = res {
-            let mut sum = 0;
-            let mut count = 0;
-            res.iter().for_each(|s| {
-                let view = s.referrer + s.unique_visitor;
-                sum += view;
-                if view > 0 {
-                    count += 1;
-                }
-            });
-            if count > 0 {
-                let rank_svg = sum / count / 10;
-                if rank_svg > 0 {
-                    return rank_svg;
-                }
-            }
+    // 按成员（可选）和日期区间读取日统计，按日期、成员排序
+    pub fn between(
+        mut conn: PooledConnection<ConnectionManager<SqliteConnection>>,
+        member: Option<i64>,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Result<Vec<Statistics>, anyhow::Error> {
+        let mut query = statistics
+            .filter(created_at.between(start, end))
+            .order_by(created_at)
+            .then_order_by(membership_id)
+            .into_boxed();
+        if let Some(member) = member {
+            query = query.filter(membership_id.eq(member));
         }
-        1
+        debug!("sql: {}", debug_query::<Sqlite, _>(&query));
+        query
+            .load::<Statistics>(&mut conn)
+            .map_err(|e| anyhow!("{:?}", e))
     }
 
     pub fn rank_between(
@@ -116,7 +130,7 @@ impl Statistics {
             .unwrap_or(Vec::new())
             .iter()
             .map(|s| (s.0, s.1))
-            .collect::<std::collections::HashMap<i64, NaiveDateTime>>();
+            .collect::<HashMap<i64, NaiveDateTime>>();
 
         let latest_referrer_at_list = statistics
             .select((
@@ -134,7 +148,7 @@ impl Statistics {
             .unwrap_or(Vec::new())
             .iter()
             .map(|s| (s.0, s.1))
-            .collect::<std::collections::HashMap<i64, NaiveDateTime>>();
+            .collect::<HashMap<i64, NaiveDateTime>>();
 
         match res {
             Ok(all) => {
@@ -147,10 +161,12 @@ impl Statistics {
                             .get(&s.0)
                             .unwrap_or(&NaiveDateTime::from_timestamp(0, 0))
                             .to_owned(),
-                        latest_referrer_at: Some(id_to_latest_referrer_at
-                            .get(&s.0)
-                            .unwrap_or(&NaiveDateTime::from_timestamp(0, 0))
-                            .to_owned()),
+                        latest_referrer_at: Some(
+                            id_to_latest_referrer_at
+                                .get(&s.0)
+                                .unwrap_or(&NaiveDateTime::from_timestamp(0, 0))
+                                .to_owned(),
+                        ),
                         membership_id: s.0,
                         unique_visitor: s.2,
                         referrer: s.3,
@@ -161,31 +177,29 @@ impl Statistics {
             Err(e) => Err(anyhow!("{:?}", e)),
         }
     }
-
-    pub fn all(
-        mut conn: PooledConnection<ConnectionManager<SqliteConnection>>,
-    ) -> Result<Vec<Statistics>, anyhow::Error> {
-        let res = statistics.load::<Statistics>(&mut conn);
-        match res {
-            Ok(all) => Ok(all),
-            Err(e) => Err(anyhow!("{:?}", e)),
-        }
-    }
 }
 
-fn load_statistics_by_created_at(
-    mut conn: PooledConnection<ConnectionManager<SqliteConnection>>,
-    _created_at: NaiveDateTime,
-) -> Result<Vec<Statistics>, anyhow::Error> {
-    debug!(
-        "sql: {}",
-        debug_query::<Sqlite, _>(&statistics.filter(created_at.eq(_created_at)))
-    );
-    let res = statistics
-        .filter(created_at.eq(_created_at))
-        .load::<Statistics>(&mut conn);
-    match res {
-        Ok(all) => Ok(all),
-        Err(e) => Err(anyhow!("{:?}", e)),
-    }
+pub(crate) fn upsert(
+    conn: &mut SqliteConnection,
+    stat: &Statistics,
+) -> Result<usize, diesel::result::Error> {
+    let statement = diesel::insert_into(statistics)
+        .values((
+            created_at.eq(stat.created_at),
+            updated_at.eq(stat.updated_at),
+            membership_id.eq(stat.membership_id),
+            unique_visitor.eq(stat.unique_visitor),
+            referrer.eq(stat.referrer),
+            latest_referrer_at.eq(stat.latest_referrer_at),
+        ))
+        .on_conflict((membership_id, created_at))
+        .do_update()
+        .set((
+            unique_visitor.eq(stat.unique_visitor),
+            referrer.eq(stat.referrer),
+            updated_at.eq(stat.updated_at),
+            latest_referrer_at.eq(stat.latest_referrer_at),
+        ));
+    debug!("sql: {}", debug_query::<Sqlite, _>(&statement));
+    statement.execute(conn)
 }
@@ -0,0 +1,530 @@
+use std::{collections::HashMap, sync::Arc};
+
+use askama::Template;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use lazy_static::lazy_static;
+
+use crate::chart::Chart;
+use crate::heatmap::{Heatmap, HeatmapRange};
+use crate::i18n::{EmbedText, Lang};
+use crate::membership_model::Membership;
+use crate::png_render::CounterState;
+use crate::sparkline::Sparkline;
+use crate::text_metrics::{ellipsize, fit_font_size, wrap};
+
+// 卡片上名称与简介的字号与可用宽度（px，x=53 至右边距），简介最多两行
+const CARD_NAME: TextFit = TextFit {
+    max_width: 251.0,
+    max_font_size: 14.0,
+};
+const CARD_DESCRIPTION: TextFit = TextFit {
+    max_width: 251.0,
+    max_font_size: 12.0,
+};
+const CARD_DESCRIPTION_LINES: usize = 2;
+
+lazy_static! {
+    // 徽章、卡片上域名与数字使用的字体
+    static ref UBUNTU_FONT: String =
+        STANDARD.encode(std::fs::read("templates/assets/fonts/ubuntu.ttf").unwrap());
+}
+
+// 联盟的品牌色
+const DEFAULT_ACCENT: &str = "#81ff7e";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThemeMode {
+    Light,
+    Dark,
+    // 跟随访客系统设置（prefers-color-scheme）
+    Auto,
+}
+
+impl ThemeMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "light" => Some(ThemeMode::Light),
+            "dark" => Some(ThemeMode::Dark),
+            "auto" => Some(ThemeMode::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThemeMode::Light => "light",
+            ThemeMode::Dark => "dark",
+            ThemeMode::Auto => "auto",
+        }
+    }
+
+    pub fn has_dark(&self) -> bool {
+        *self != ThemeMode::Light
+    }
+
+    pub fn is_auto(&self) -> bool {
+        *self == ThemeMode::Auto
+    }
+}
+
+// #rgb、#rrggbb，井号可省略；统一为小写的 #rrggbb，可以直接写入 CSS
+pub fn parse_accent(s: &str) -> Option<String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = hex.to_ascii_lowercase();
+    match hex.len() {
+        3 => Some(format!(
+            "#{}",
+            hex.chars().flat_map(|c| [c, c]).collect::<String>()
+        )),
+        6 => Some(format!("#{}", hex)),
+        _ => None,
+    }
+}
+
+pub struct Theme {
+    pub mode: ThemeMode,
+    pub accent: String,
+}
+
+impl Theme {
+    // 图片无法跟随访客的系统设置，auto 按 light 渲染
+    pub fn rasterized(self) -> Self {
+        match self.mode {
+            ThemeMode::Auto => Theme {
+                mode: ThemeMode::Light,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+// ?theme=light|dark|auto&accent=，未指定的项取成员在 membership.json 中的设置，再取缺省值
+#[derive(Debug, Clone, Default)]
+pub struct ThemeQuery {
+    mode: Option<ThemeMode>,
+    accent: Option<String>,
+}
+
+impl ThemeQuery {
+    pub fn parse(query: &HashMap<String, String>) -> Result<Self, String> {
+        let get = |key: &str| query.get(key).map(|v| v.trim()).filter(|v| !v.is_empty());
+        Ok(ThemeQuery {
+            mode: match get("theme") {
+                Some(t) => {
+                    Some(ThemeMode::parse(t).ok_or_else(|| {
+                        format!("invalid theme {}, expected light, dark or auto", t)
+                    })?)
+                }
+                None => None,
+            },
+            accent: match get("accent") {
+                Some(a) => Some(
+                    parse_accent(a)
+                        .ok_or_else(|| format!("invalid accent {}, expected a hex color", a))?,
+                ),
+                None => None,
+            },
+        })
+    }
+
+    // 成员设置有误时忽略
+    pub fn resolve(&self, member: &Membership) -> Theme {
+        Theme {
+            mode: self
+                .mode
+                .or_else(|| member.theme.as_deref().and_then(ThemeMode::parse))
+                .unwrap_or(ThemeMode::Auto),
+            accent: self
+                .accent
+                .clone()
+                .or_else(|| member.accent.as_deref().and_then(parse_accent))
+                .unwrap_or_else(|| DEFAULT_ACCENT.to_string()),
+        }
+    }
+}
+
+// 文字排版规则：按内置字体的字形宽度计算，可用宽度为 max_width（px）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextFit {
+    pub max_width: f32,
+    pub max_font_size: f32,
+}
+
+impl TextFit {
+    // 放不下时缩小字号，用于域名
+    pub fn font_size(&self, text: &str) -> f32 {
+        fit_font_size(text, self.max_width, self.max_font_size)
+    }
+
+    // 放不下时以省略号截断，用于名称
+    pub fn ellipsize(&self, text: &str) -> String {
+        ellipsize(text, self.max_font_size, self.max_width)
+    }
+}
+
+const CARD_DOMAIN: TextFit = TextFit {
+    max_width: 288.0,
+    max_font_size: 36.0,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BadgeStyle {
+    // 150×40，徽标、域名与三项数据
+    Classic,
+    // 130×20，域名与独立访客数
+    Pill,
+    // 468×60 横幅
+    Banner,
+    // 120×240 侧边栏
+    Sidebar,
+    // 100×100 方块
+    Tile,
+}
+
+// 徽章样式，模板为 templates/svg/badge/<name>.svg
+#[derive(Debug)]
+pub struct BadgeLayout {
+    pub name: &'static str,
+    pub style: BadgeStyle,
+    pub domain: TextFit,
+    // 成员名称的字号与宽度，与模板一致；不显示名称时为 None
+    pub member_name: Option<TextFit>,
+}
+
+pub static BADGE_LAYOUTS: [BadgeLayout; 5] = [
+    BadgeLayout {
+        name: "classic",
+        style: BadgeStyle::Classic,
+        domain: TextFit {
+            max_width: 96.0,
+            max_font_size: 14.0,
+        },
+        member_name: None,
+    },
+    BadgeLayout {
+        name: "pill",
+        style: BadgeStyle::Pill,
+        domain: TextFit {
+            max_width: 80.0,
+            max_font_size: 11.0,
+        },
+        member_name: None,
+    },
+    BadgeLayout {
+        name: "banner",
+        style: BadgeStyle::Banner,
+        domain: TextFit {
+            max_width: 236.0,
+            max_font_size: 24.0,
+        },
+        member_name: Some(TextFit {
+            max_width: 236.0,
+            max_font_size: 12.0,
+        }),
+    },
+    BadgeLayout {
+        name: "sidebar",
+        style: BadgeStyle::Sidebar,
+        domain: TextFit {
+            max_width: 104.0,
+            max_font_size: 16.0,
+        },
+        member_name: Some(TextFit {
+            max_width: 104.0,
+            max_font_size: 11.0,
+        }),
+    },
+    BadgeLayout {
+        name: "tile",
+        style: BadgeStyle::Tile,
+        domain: TextFit {
+            max_width: 88.0,
+            max_font_size: 14.0,
+        },
+        member_name: None,
+    },
+];
+
+impl BadgeLayout {
+    // ?style=，缺省为 classic
+    pub fn parse(query: &HashMap<String, String>) -> Result<&'static BadgeLayout, String> {
+        match query
+            .get("style")
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+        {
+            None => Ok(&BADGE_LAYOUTS[0]),
+            Some(style) => BADGE_LAYOUTS
+                .iter()
+                .find(|l| l.name == style)
+                .ok_or_else(|| {
+                    format!(
+                        "invalid style {}, expected one of {}",
+                        style,
+                        BADGE_LAYOUTS
+                            .iter()
+                            .map(|l| l.name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                }),
+        }
+    }
+}
+
+// 模板中的文本按 XML 转义，字体与头像为 base64，原样输出
+#[derive(Template)]
+#[template(path = "svg/badge.svg", escape = "html")]
+pub struct BadgeSvg {
+    layout: &'static BadgeLayout,
+    domain: String,
+    name: String,
+    font_size: f32,
+    unique_visitor: i64,
+    referrer: i64,
+    level: i64,
+    font: &'static str,
+    theme: Theme,
+    lang: &'static str,
+    text: &'static EmbedText,
+}
+
+impl BadgeSvg {
+    pub fn new(
+        layout: &'static BadgeLayout,
+        member: &Membership,
+        (unique_visitor, referrer, level): CounterState,
+        theme: Theme,
+        lang: Lang,
+    ) -> Self {
+        BadgeSvg {
+            layout,
+            domain: member.domain.clone(),
+            name: layout
+                .member_name
+                .map(|fit| fit.ellipsize(member.name_in(lang)))
+                .unwrap_or_default(),
+            font_size: layout.domain.font_size(&member.domain),
+            unique_visitor,
+            referrer,
+            level,
+            font: UBUNTU_FONT.as_str(),
+            theme,
+            lang: lang.as_str(),
+            text: lang.text(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "svg/card.svg", escape = "html")]
+pub struct CardSvg {
+    name: String,
+    description: Vec<String>,
+    domain: String,
+    font_size: f32,
+    // 缩放后的 PNG，base64
+    avatar: Arc<String>,
+    trend: Sparkline,
+    unique_visitor: i64,
+    referrer: i64,
+    level: i64,
+    font: &'static str,
+    theme: Theme,
+    lang: &'static str,
+    text: &'static EmbedText,
+}
+
+impl CardSvg {
+    pub fn new(
+        member: &Membership,
+        avatar: Arc<String>,
+        (unique_visitor, referrer, level): CounterState,
+        trend: Sparkline,
+        theme: Theme,
+        lang: Lang,
+    ) -> Self {
+        CardSvg {
+            name: CARD_NAME.ellipsize(member.name_in(lang)),
+            description: wrap(
+                member.description_in(lang),
+                CARD_DESCRIPTION.max_font_size,
+                CARD_DESCRIPTION.max_width,
+                CARD_DESCRIPTION_LINES,
+            ),
+            domain: member.domain.clone(),
+            font_size: CARD_DOMAIN.font_size(&member.domain),
+            avatar,
+            trend,
+            unique_visitor,
+            referrer,
+            level,
+            font: UBUNTU_FONT.as_str(),
+            theme,
+            lang: lang.as_str(),
+            text: lang.text(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "svg/icon.svg", escape = "html")]
+pub struct IconSvg {
+    theme: Theme,
+    lang: &'static str,
+    text: &'static EmbedText,
+}
+
+impl IconSvg {
+    pub fn new(theme: Theme, lang: Lang) -> Self {
+        IconSvg {
+            theme,
+            lang: lang.as_str(),
+            text: lang.text(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "svg/heatmap.svg", escape = "html")]
+pub struct HeatmapSvg {
+    domain: String,
+    caption: String,
+    heatmap: Heatmap,
+    theme: Theme,
+    lang: &'static str,
+    text: &'static EmbedText,
+}
+
+impl HeatmapSvg {
+    pub fn new(
+        member: &Membership,
+        range: HeatmapRange,
+        heatmap: Heatmap,
+        theme: Theme,
+        lang: Lang,
+    ) -> Self {
+        let text = lang.text();
+        let caption = match range {
+            HeatmapRange::LastYear => text.heatmap_last_year.to_string(),
+            HeatmapRange::Year(year) => text.heatmap_year.replace("{year}", &year.to_string()),
+        };
+        HeatmapSvg {
+            domain: member.domain.clone(),
+            caption: caption.replace("{total}", &heatmap.total.to_string()),
+            heatmap,
+            theme,
+            lang: lang.as_str(),
+            text,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "svg/chart.svg", escape = "html")]
+pub struct ChartSvg {
+    domain: String,
+    chart: Chart,
+    theme: Theme,
+    lang: &'static str,
+}
+
+impl ChartSvg {
+    pub fn new(member: &Membership, chart: Chart, theme: Theme, lang: Lang) -> Self {
+        ChartSvg {
+            domain: member.domain.clone(),
+            chart,
+            theme,
+            lang: lang.as_str(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use super::*;
+
+    // 名称与简介含需要转义的字符，计数中含 1233
+    fn member() -> Membership {
+        serde_json::from_value(serde_json::json!({
+            "domain": "a-b.example",
+            "name": "A<B & \"C\" 1233",
+            "description": "<script>alert(1233)</script> & \"quoted\" text that wraps onto the next line",
+            "github_username": "",
+            "hidden": null,
+            "joined_at": null,
+            "theme": null,
+            "accent": null,
+        }))
+        .unwrap()
+    }
+
+    fn theme(mode: ThemeMode) -> Theme {
+        Theme {
+            mode,
+            accent: DEFAULT_ACCENT.to_string(),
+        }
+    }
+
+    // UPDATE_GOLDEN=1 cargo test 重新生成 tests/golden 下的文件
+    fn assert_golden(name: &str, svg: String) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(name);
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&path, &svg).unwrap();
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        assert!(svg == expected, "{} differs from {}", name, path.display());
+        assert!(!svg.contains("<B") && !svg.contains("<script"));
+    }
+
+    #[test]
+    fn badge_matches_golden() {
+        // sidebar 带成员名称
+        for name in ["classic", "sidebar"] {
+            let layout = BADGE_LAYOUTS.iter().find(|l| l.name == name).unwrap();
+            let svg = BadgeSvg::new(
+                layout,
+                &member(),
+                (1233, 45, 3),
+                theme(ThemeMode::Light),
+                Lang::En,
+            )
+            .render()
+            .unwrap();
+            assert!(svg.contains("1233"));
+            assert_golden(&format!("badge-{}.svg", layout.name), svg);
+        }
+    }
+
+    #[test]
+    fn card_matches_golden() {
+        let svg = CardSvg::new(
+            &member(),
+            Arc::new("iVBORw0KGgo=".to_string()),
+            (1233, 45, 3),
+            Sparkline::build(&[(1, 0), (1233, 45), (7, 2)]),
+            theme(ThemeMode::Dark),
+            Lang::En,
+        )
+        .render()
+        .unwrap();
+        assert!(svg.contains("A&lt;B &amp; &quot;C&quot; 1233"));
+        assert_golden("card.svg", svg);
+    }
+
+    #[test]
+    fn icon_matches_golden() {
+        let svg = IconSvg::new(theme(ThemeMode::Auto), Lang::Zh)
+            .render()
+            .unwrap();
+        assert_golden("icon.svg", svg);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use unicode_width::UnicodeWidthChar;
+
+// 内置字体中没有的字形：全角字符按 1em，其余按 0.6em 估算。
+// ubuntu.ttf 是只含拉丁字母的子集，汉字等实际由访客的回退字体绘制，宽度只是近似值，
+// 常见中文字体的汉字宽度为 1em，偏差一般在几个像素以内
+const WIDE_ADVANCE: f32 = 1.0;
+const NARROW_ADVANCE: f32 = 0.6;
+const ELLIPSIS: char = '…';
+
+lazy_static! {
+    static ref METRICS: FontMetrics =
+        FontMetrics::load(&std::fs::read("templates/assets/fonts/ubuntu.ttf").unwrap());
+}
+
+// 内置 Ubuntu 字体各字形的宽度，以 em 计
+struct FontMetrics {
+    advances: HashMap<char, f32>,
+}
+
+impl FontMetrics {
+    fn load(data: &[u8]) -> Self {
+        let mut advances = HashMap::new();
+        if let Ok(face) = ttf_parser::Face::parse(data, 0) {
+            let units = face.units_per_em() as f32;
+            if let Some(cmap) = face.tables().cmap {
+                for subtable in cmap.subtables.into_iter().filter(|s| s.is_unicode()) {
+                    subtable.codepoints(|cp| {
+                        let advance = char::from_u32(cp)
+                            .zip(subtable.glyph_index(cp))
+                            .and_then(|(c, glyph)| Some((c, face.glyph_hor_advance(glyph)?)));
+                        if let Some((c, advance)) = advance {
+                            advances.insert(c, advance as f32 / units);
+                        }
+                    });
+                }
+            }
+        }
+        FontMetrics { advances }
+    }
+
+    fn advance(&self, c: char) -> f32 {
+        match self.advances.get(&c) {
+            Some(advance) => *advance,
+            None => match c.width() {
+                None | Some(0) => 0.0,
+                Some(1) => NARROW_ADVANCE,
+                Some(_) => WIDE_ADVANCE,
+            },
+        }
+    }
+}
+
+// 文字在给定字号下的宽度（px）
+pub fn text_width(text: &str, font_size: f32) -> f32 {
+    text.chars().map(|c| METRICS.advance(c)).sum::<f32>() * font_size
+}
+
+// 不超过 max_font_size、且能放进 max_width 的字号，保留一位小数
+pub fn fit_font_size(text: &str, max_width: f32, max_font_size: f32) -> f32 {
+    let width = text_width(text, 1.0);
+    if width * max_font_size <= max_width {
+        return max_font_size;
+    }
+    ((max_width / width) * 10.0).floor() / 10.0
+}
+
+// 超出宽度时截断并以省略号结尾
+pub fn ellipsize(text: &str, font_size: f32, max_width: f32) -> String {
+    if text_width(text, font_size) <= max_width {
+        return text.to_string();
+    }
+    let budget = max_width - METRICS.advance(ELLIPSIS) * font_size;
+    let mut res = String::new();
+    let mut width = 0.0;
+    for c in text.chars() {
+        width += METRICS.advance(c) * font_size;
+        if width > budget {
+            break;
+        }
+        res.push(c);
+    }
+    res.truncate(res.trim_end().len());
+    res.push(ELLIPSIS);
+    res
+}
+
+// 不出现在行首的标点
+const NO_BREAK_BEFORE: &str = "，。、；：！？）》」』,.;:!?)";
+
+// 可以换行的片段：拉丁单词（含其后的空格）为一段，汉字等其他字符各自为一段，标点跟在前一段之后
+fn segments(text: &str) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    for (i, c) in text.char_indices() {
+        if let Some(p) = prev {
+            let joined = c.is_whitespace()
+                || NO_BREAK_BEFORE.contains(c)
+                || (!p.is_whitespace() && p.is_ascii() && c.is_ascii());
+            if !joined {
+                res.push(&text[start..i]);
+                start = i;
+            }
+        }
+        prev = Some(c);
+    }
+    if start < text.len() {
+        res.push(&text[start..]);
+    }
+    res
+}
+
+// 按宽度折行，最多 max_lines 行，放不下时最后一行以省略号结尾；过长的单词按字符断开
+pub fn wrap(text: &str, font_size: f32, max_width: f32, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    let mut pieces: Vec<String> = Vec::new();
+    for segment in segments(text.trim()) {
+        if text_width(segment.trim_end(), font_size) <= max_width {
+            pieces.push(segment.to_string());
+        } else {
+            pieces.extend(segment.chars().map(|c| c.to_string()));
+        }
+    }
+    for (i, piece) in pieces.iter().enumerate() {
+        let candidate = format!("{}{}", line, piece);
+        if line.is_empty() || text_width(candidate.trim_end(), font_size) <= max_width {
+            line = candidate;
+            continue;
+        }
+        if lines.len() + 1 == max_lines {
+            // 最后一行：把剩余内容接上后截断
+            let rest: String = pieces[i..].concat();
+            lines.push(ellipsize(
+                &format!("{}{}", line, rest),
+                font_size,
+                max_width,
+            ));
+            return lines;
+        }
+        lines.push(line.trim_end().to_string());
+        line = piece.to_string();
+    }
+    if !line.trim().is_empty() {
+        lines.push(line.trim_end().to_string());
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin_uses_font_metrics() {
+        assert!(text_width("i", 10.0) < text_width("W", 10.0));
+        assert_eq!(
+            text_width("ab", 10.0),
+            text_width("a", 10.0) + text_width("b", 10.0)
+        );
+        let max_width = text_width("hello world", 10.0);
+        assert_eq!(
+            wrap("hello world foo", 10.0, max_width, 3),
+            ["hello world", "foo"]
+        );
+    }
+
+    // 内置字体没有汉字，按 1em 估算
+    #[test]
+    fn cjk_is_estimated_as_full_width() {
+        assert_eq!(text_width("汉字", 12.0), 24.0);
+        assert_eq!(wrap("一二三四五", 10.0, 30.0, 3), ["一二三", "四五"]);
+    }
+
+    #[test]
+    fn mixed_text_breaks_between_word_and_cjk() {
+        assert_eq!(
+            text_width("a汉", 10.0),
+            text_width("a", 10.0) + WIDE_ADVANCE * 10.0
+        );
+        let max_width = text_width("Hi 你好", 10.0);
+        assert_eq!(wrap("Hi 你好世界", 10.0, max_width, 3), ["Hi 你好", "世界"]);
+    }
+
+    #[test]
+    fn empty_text() {
+        assert_eq!(text_width("", 10.0), 0.0);
+        assert_eq!(fit_font_size("", 10.0, 16.0), 16.0);
+        assert_eq!(ellipsize("", 10.0, 10.0), "");
+        assert!(wrap("", 10.0, 100.0, 2).is_empty());
+        assert!(wrap("   ", 10.0, 100.0, 2).is_empty());
+    }
+
+    // 放不进一行的单词按字符断开
+    #[test]
+    fn over_long_word_is_split_by_chars() {
+        let word = "supercalifragilisticexpialidocious";
+        let max_width = text_width("supercalif", 10.0);
+        let lines = wrap(word, 10.0, max_width, 10);
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| text_width(l, 10.0) <= max_width));
+        assert_eq!(lines.concat(), word);
+    }
+
+    #[test]
+    fn last_line_is_ellipsized() {
+        let lines = wrap("一二三四五六七八九十", 10.0, 30.0, 2);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "一二三");
+        assert!(lines[1].ends_with(ELLIPSIS));
+        assert!(text_width(&lines[1], 10.0) <= 30.0);
+
+        let shrunk = fit_font_size("example.com", 40.0, 16.0);
+        assert!(shrunk < 16.0 && text_width("example.com", shrunk) <= 40.0);
+    }
+
+    // 逗号不出现在行首，与前一个字一起换到下一行
+    #[test]
+    fn punctuation_stays_with_previous_char() {
+        assert_eq!(
+            wrap("一二三，四五六", 10.0, 30.0, 3),
+            ["一二", "三，四", "五六"]
+        );
+        assert_eq!(
+            wrap("one two, three", 10.0, text_width("one two", 10.0), 3)[0],
+            "one"
+        );
+    }
+}